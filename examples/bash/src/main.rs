@@ -1,19 +1,17 @@
 use std::path::PathBuf;
 
-use maplit::hashset;
-
 use m6lexerkit::{
     make_token_matcher_rules,
     SrcFileInfo,
-    dqstr_m,
-    heredoc_m,
-    tokenize, TokenMatchResult,
+    prelude::{dqstr_m, heredoc_m},
+    tokenize, TokenList, TokenMatchResult,
 };
 
 
 make_token_matcher_rules! {
     id        => "[[:alpha:]_][[:alnum:]_]*",
     exec_id   => r"\$[[:alpha:]_][[:alnum:]_]*",
+    shebang => @m6lexerkit::prelude::shebang_tok_m,
     sharp_line_comment  => r"#.*",
 
     sp      => "[[:blank:]]+",
@@ -48,38 +46,75 @@ make_token_matcher_rules! {
 
 
 fn trim_tokens(tokens: &[Token]) -> Vec<Token> {
-    let blank_set = hashset! { "newline", "sp", "sharp_line_comment" };
-
     tokens
     .iter()
-    .filter(|tok| !blank_set.contains(&tok.name_str().as_str()))
+    .filter(|tok| !tok.is_trivia())
     .copied()
     .collect::<Vec<Token>>()
 }
 
 
-fn display_pure_tok(tokens: &[Token]) {
-    for token in tokens.iter() {
-        println!("{}", token.value_str())
-    }
-}
-
-
 fn main() {
 
     for i in 0..1 {
         let path = PathBuf::from(format!("./examples/exp{}.sh", i));
-        let srcfile = SrcFileInfo::new(path).unwrap();
+        let srcfile = SrcFileInfo::new(&path).unwrap();
 
         // println!("{:#?}", sp_m(srcfile.get_srcstr(), SrcLoc { ln: 0, col: 0 }));
 
         match tokenize(&srcfile, &MATCHERS[..]) {
             Ok(tokens) => {
                 let trimed_tokens = trim_tokens(&tokens[..]);
-                display_pure_tok(&trimed_tokens[..]);
+                print!("{}", TokenList(&trimed_tokens));
             },
             Err(err) => println!("{}", err),
         }
     }
 
 }
+
+
+#[cfg(test)]
+mod tests {
+    use m6lexerkit::{prelude::token_histogram, tokenize, SrcFileInfo};
+
+    use super::MATCHERS;
+
+    #[test]
+    fn test_token_histogram() {
+        let srcfile = SrcFileInfo::from_str(
+            "# pipes\nlocal a = $b\necho \"$a\"\n".to_owned(),
+        );
+        let tokens = tokenize(&srcfile, &MATCHERS[..]).unwrap();
+        let hist = token_histogram(&tokens);
+
+        assert_eq!(hist["id"], 3);
+        assert_eq!(hist["sp"], 4);
+        assert_eq!(hist["exec_id"], 1);
+        assert_eq!(hist["newline"], 3);
+    }
+
+    #[test]
+    fn test_shebang() {
+        let srcfile = SrcFileInfo::from_str(
+            "#!/bin/bash\n# setup\necho a # trailing\n#!not shebang\n"
+                .to_owned(),
+        );
+        let tokens = tokenize(&srcfile, &MATCHERS[..]).unwrap();
+
+        assert!(tokens[0].check_name("shebang"));
+        assert!(tokens[0].check_value("#!/bin/bash"));
+        assert_eq!(tokens[0].span(), m6lexerkit::Span { from: 0, end: 11 });
+
+        let comments = tokens
+            .iter()
+            .filter(|tok| tok.check_name("sharp_line_comment"))
+            .map(|tok| tok.value_string())
+            .collect::<Vec<_>>();
+        assert_eq!(comments, ["# setup", "# trailing", "#!not shebang"]);
+        assert_eq!(
+            tokens.iter().filter(|tok| tok.check_name("shebang")).count(),
+            1
+        );
+    }
+}
@@ -4,8 +4,8 @@ use std::path::PathBuf;
 use m6lexerkit::{
     make_token_matcher_rules,
     SrcFileInfo,
-    aux_strlike_m,
-    tokenize, TokenMatchResult,
+    prelude::balanced_m,
+    tokenize, Span, TokenMatchResult,
 };
 
 
@@ -13,11 +13,11 @@ make_token_matcher_rules! {
     ellipsis2 => "\\.\\.",
 
     id     => "[[:alpha:]_][[:alnum:]_]*",
-    lit_int => r"[+|-]?(([0-9]+)|(0x[0-9a-f]+))",
-    lit_float => r"[+|-]?([0-9]+\.[0-9])",
+    lit_int => r"[+-]?(([0-9]+)|(0x[0-9a-f]+))",
+    lit_float => r"[+-]?([0-9]+\.[0-9])",
 
     cmd,
-    slash_block_comment => r"/\*.*",
+    slash_block_comment => @m6lexerkit::prelude::block_comment_m("/*", "*/"),
     slash_line_comment  => r"//.*",
 
     sp      => "[[:space:]--[\n\r]]+",
@@ -47,11 +47,14 @@ make_token_matcher_rules! {
 }
 
 
+/// `!( ... )`, nested parentheses included
 fn cmd_m(source: &str, from: usize) -> Option<TokenMatchResult> {
-    aux_strlike_m(source, from, "!(", ")", '\\')
-    .and_then(|res|
-        Some(res.and_then(|tok| Ok(tok.rename("cmd"))))
-    )
+    let rest = source.strip_prefix('!')?;
+
+    balanced_m(rest, from + 1, '(', ')', Some('\\'))
+    .map(|res| res.map(|tok| {
+        tok.with_span(Span { from, end: tok.span.end }).rename("cmd")
+    }))
 }
 
 
@@ -60,7 +63,7 @@ fn main() {
 
     for i in 0..1 {
         let path = PathBuf::from(format!("./examples/exp{}", i));
-        let srcfile = SrcFileInfo::new(path).unwrap();
+        let srcfile = SrcFileInfo::new(&path).unwrap();
 
         // println!("{:#?}", sp_m(srcfile.get_srcstr(), SrcLoc { ln: 0, col: 0 }));
 
@@ -71,3 +74,33 @@ fn main() {
     }
 
 }
+
+
+#[cfg(test)]
+mod tests {
+    use m6lexerkit::{tokenize, SrcFileInfo};
+
+    use super::MATCHERS;
+
+    #[test]
+    fn test_multiline_block_comment() {
+        let srcfile =
+            SrcFileInfo::from_str("/* line1\n line2 */\nx".to_owned());
+        let tokens = tokenize(&srcfile, &MATCHERS[..]).unwrap();
+
+        assert_eq!(tokens.len(), 3);
+        assert!(tokens[0].check_name("slash_block_comment"));
+        assert!(tokens[0].check_value("/* line1\n line2 */"));
+        assert!(tokens[2].check_name("id"));
+    }
+
+    #[test]
+    fn test_nested_cmd() {
+        let srcfile = SrcFileInfo::from_str("!(ls (a) b)\nx".to_owned());
+        let tokens = tokenize(&srcfile, &MATCHERS[..]).unwrap();
+
+        assert!(tokens[0].check_name("cmd"));
+        assert!(tokens[0].check_value("ls (a) b"));
+        assert_eq!(tokens[0].span().end, 11);
+    }
+}
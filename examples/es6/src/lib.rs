@@ -4,56 +4,220 @@ mod tokenizer1;
 mod tokenizer2;
 
 use m6lexerkit::Token;
-use maplit::hashset;
 
 pub use tokenizer1::tokenize as tokenize1;
+pub use tokenizer1::tokenize_into as tokenize1_into;
 pub use tokenizer2::tokenize as tokenize2;
 
 #[allow(unused)]
 pub(crate) fn trim_tokens(tokens: &[Token]) -> Vec<Token> {
-    let blank_set = hashset! { "newline", "sp", "slash_line_comment" };
-
     tokens
     .iter()
-    .filter(|tok| !blank_set.contains(&tok.name_str().as_str()))
+    .filter(|tok| !tok.is_trivia())
     .copied()
     .collect::<Vec<Token>>()
 }
 
-#[allow(unused)]
-pub(crate) fn display_pure_tok(tokens: &[Token]) {
-    for token in tokens.iter() {
-        println!("{}", token.value_str())
-    }
-}
-
-
 #[cfg(test)]
 mod tests {
 
     extern crate test;
-    use std::path::PathBuf;
+    use std::{cmp::min, path::PathBuf};
 
-    use m6lexerkit::SrcFileInfo;
+    use m6lexerkit::{
+        compiled_regex, CharMatcher, Regex, RegexCharMatcher, Span, SrcFileInfo,
+    };
     use test::Bencher;
 
     use crate::tokenize1;
+    use crate::tokenize1_into;
     use crate::tokenize2;
+    use crate::tokenizer2::RECOGNIZER;
 
     #[bench]
     fn bench_tokenizer1(b: &mut Bencher) {
         let srcfile
-        = SrcFileInfo::new(PathBuf::from("./examples/app.js")).unwrap();
+        = SrcFileInfo::new(&PathBuf::from("./examples/app.js")).unwrap();
 
         b.iter(|| {
             let _tokens = tokenize1(&srcfile).unwrap();
         });
     }
 
+    /// A batch of small (one statement) files
+    fn small_srcfiles() -> Vec<SrcFileInfo> {
+        (0..1000)
+        .map(|i| SrcFileInfo::from_str(format!("let v{i} = f(a, b) + {i};\n")))
+        .collect()
+    }
+
+    #[bench]
+    fn bench_tokenizer1_small_files(b: &mut Bencher) {
+        let srcfiles = small_srcfiles();
+
+        b.iter(|| {
+            for srcfile in srcfiles.iter() {
+                let _tokens = tokenize1(srcfile).unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_tokenizer1_into_small_files(b: &mut Bencher) {
+        let srcfiles = small_srcfiles();
+        let mut tokens = vec![];
+
+        b.iter(|| {
+            for srcfile in srcfiles.iter() {
+                tokens.clear();
+                tokenize1_into(srcfile, &mut tokens).unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_regex_char_matcher(b: &mut Bencher) {
+        let srcfile
+        = SrcFileInfo::new(&PathBuf::from("./examples/app.js")).unwrap();
+        let matcher = RegexCharMatcher::new("[[:alnum:]_]");
+
+        b.iter(|| {
+            srcfile
+            .get_srcstr()
+            .chars()
+            .filter(|c| matcher.is_match(*c))
+            .count()
+        });
+    }
+
+    /// `bench_regex_char_matcher` without the ASCII table
+    #[bench]
+    fn bench_regex_char_matcher_uncached(b: &mut Bencher) {
+        let srcfile
+        = SrcFileInfo::new(&PathBuf::from("./examples/app.js")).unwrap();
+        let pat = Regex::new("[[:alnum:]_]").unwrap();
+        let mut buf = [0u8; 4];
+
+        b.iter(|| {
+            srcfile
+            .get_srcstr()
+            .chars()
+            .filter(|c| pat.is_match(c.encode_utf8(&mut buf)))
+            .count()
+        });
+    }
+
+    /// A few patterns of tokenizer1 as the rules macro anchors them
+    const RULE_PATTERNS: &[&str] = &[
+        r"^([[:alpha:]_][[:alnum:]_]*)",
+        r"^([+-]?(([0-9]+)|(0x[0-9a-f]+)))",
+        r"^(//.*)",
+        r"^([[:blank:]]+)",
+        r"^(\r?\n)",
+    ];
+
+    /// Second rule set over the same patterns, served by the registry
+    #[bench]
+    fn bench_compiled_regex(b: &mut Bencher) {
+        b.iter(|| {
+            for patstr in RULE_PATTERNS {
+                let _pat = compiled_regex(patstr).unwrap();
+            }
+        });
+    }
+
+    /// `bench_compiled_regex` compiling each pattern again
+    #[bench]
+    fn bench_compiled_regex_uncached(b: &mut Bencher) {
+        b.iter(|| {
+            for patstr in RULE_PATTERNS {
+                let _pat = Regex::new(patstr).unwrap();
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_span_chars_count(b: &mut Bencher) {
+        let srcfile
+        = SrcFileInfo::new(&PathBuf::from("./examples/app.js")).unwrap();
+        let tokens = tokenize1(&srcfile).unwrap();
+
+        b.iter(|| {
+            tokens
+            .iter()
+            .map(|tok| tok.span_chars_count(srcfile.get_srcstr()))
+            .sum::<usize>()
+        });
+    }
+
+    #[bench]
+    fn bench_span_chars_count_unmemoized(b: &mut Bencher) {
+        let srcfile
+        = SrcFileInfo::new(&PathBuf::from("./examples/app.js")).unwrap();
+        let tokens = tokenize1(&srcfile)
+        .unwrap()
+        .into_iter()
+        .map(|tok| tok.with_span(tok.span))
+        .collect::<Vec<_>>();
+
+        b.iter(|| {
+            tokens
+            .iter()
+            .map(|tok| tok.span_chars_count(srcfile.get_srcstr()))
+            .sum::<usize>()
+        });
+    }
+
+    /// Token spans of `app.js` for the recognizer benches
+    /// (not by `tokenize2`, `LEX_DFA_MAP` is bound to the interner of the
+    /// thread first using it)
+    fn app_spans() -> (SrcFileInfo, Vec<Span>) {
+        let srcfile
+        = SrcFileInfo::new(&PathBuf::from("./examples/app.js")).unwrap();
+        let spans = tokenize1(&srcfile)
+        .unwrap()
+        .iter()
+        .map(|tok| tok.span())
+        .collect();
+
+        (srcfile, spans)
+    }
+
+    #[bench]
+    fn bench_recognize(b: &mut Bencher) {
+        let (srcfile, spans) = app_spans();
+
+        b.iter(|| {
+            for span in spans.iter() {
+                let _tok = RECOGNIZER.recognize(srcfile.get_srcstr(), *span);
+            }
+        });
+    }
+
+    /// Try the patterns one by one (`recognize` before the `RegexSet`)
+    #[bench]
+    fn bench_recognize_linear(b: &mut Bencher) {
+        let (srcfile, spans) = app_spans();
+        let source = srcfile.get_srcstr();
+
+        b.iter(|| {
+            for span in spans.iter() {
+                let end = min(span.end, span.from + RECOGNIZER.lookhead);
+                let end = (end..).find(|i| source.is_char_boundary(*i)).unwrap();
+
+                let _name = RECOGNIZER
+                .pat_items()
+                .iter()
+                .find(|(pat, _)| pat.is_match(&source[span.from..end]))
+                .map(|(_, name)| *name);
+            }
+        });
+    }
+
     #[bench]
     fn bench_tokenizer2(b: &mut Bencher) {
         let srcfile
-        = SrcFileInfo::new(PathBuf::from("./examples/app.js")).unwrap();
+        = SrcFileInfo::new(&PathBuf::from("./examples/app.js")).unwrap();
 
         b.iter(|| {
             let _tokens = tokenize2(&srcfile).unwrap();
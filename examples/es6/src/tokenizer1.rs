@@ -1,11 +1,9 @@
 use m6lexerkit::{
     make_token_matcher_rules,
     SrcFileInfo,
-    sqstr_m,
-    dqstr_m,
-    aqstr_m,
-    lit_regex_m,
-    tokenize as tokenize_, TokenMatchResult, TokenizeResult,
+    prelude::{sqstr_m, dqstr_m, aqstr_m, lit_regex_m},
+    tokenize as tokenize_, tokenize_into as tokenize_into_, TokenMatchResult,
+    TokenizeError, TokenizeResult,
 };
 
 
@@ -13,8 +11,8 @@ make_token_matcher_rules! {
     id        => "[[:alpha:]_][[:alnum:]_]*",
 
     // Lit
-    lit_int => r"[+|-]?(([0-9]+)|(0x[0-9a-f]+))",
-    lit_float => r"[+|-]?([0-9]+\.[0-9])",
+    lit_int => r"[+-]?(([0-9]+)|(0x[0-9a-f]+))",
+    lit_float => r"[+-]?([0-9]+\.[0-9])",
     sqstr,
     dqstr,
     aqstr,
@@ -52,7 +50,7 @@ make_token_matcher_rules! {
 
     // Binary Operation
     sub    => "-",
-    add    => r"\+[^\+]",
+    add    => r"\+" / r"[^\+]|$",
     mul    => r"\*",
     div    => "/",
     dot    => r"\.",
@@ -76,28 +74,36 @@ pub fn tokenize(source: &SrcFileInfo) -> TokenizeResult {
     tokenize_(source, &MATCHERS[..])
 }
 
+#[inline]
+pub fn tokenize_into(
+    source: &SrcFileInfo,
+    tokens: &mut Vec<Token>,
+) -> Result<(), TokenizeError> {
+    tokenize_into_(source, &MATCHERS[..], tokens)
+}
+
 
 
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
 
-    use m6lexerkit::SrcFileInfo;
+    use m6lexerkit::{SrcFileInfo, TokenList};
 
-    use crate::{trim_tokens, display_pure_tok, tokenize1};
+    use crate::{trim_tokens, tokenize1};
 
 
     #[test]
     fn test_tokenize1() {
         let path = PathBuf::from("./examples/exp0.js");
-        let srcfile = SrcFileInfo::new(path).unwrap();
+        let srcfile = SrcFileInfo::new(&path).unwrap();
 
         // println!("{:#?}", sp_m(srcfile.get_srcstr(), SrcLoc { ln: 0, col: 0 }));
 
         match tokenize1(&srcfile) {
             Ok(tokens) => {
                 let tokens = trim_tokens(&tokens[..]);
-                display_pure_tok(&tokens[..]);
+                print!("{}", TokenList(&tokens));
             },
             Err(err) => println!("{}", err),
         }
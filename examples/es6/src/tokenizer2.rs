@@ -6,10 +6,10 @@ use m6lexerkit::{
 make_char_matcher_rules! {
     ident       => "[[:alnum:]_]"    | r,
     ident_head  => "[[:alpha:]_]"    | r,
-    delimiter   => "[,|;|:]"         | r,
+    delimiter   => "[,;:]"           | r,
     num         => "[[:digit:]]"     | r,
-    numsign     => "[+|-]"           | r,
-    op          => r#"[\+|\-|\*|/|%|\^|\||&|~|!|?|@|>|=|<|\.]"# | r,
+    numsign     => "[+-]"            | r,
+    op          => "[-+*/%^|&~!?@>=<.]" | r,
     any         => r#"[\d\D]"#       | r,
     ng          => r#"[^[:graph:]]"# | r,
     sp          => r#"[[:space:]]"#  | r,
@@ -98,7 +98,7 @@ lazy_static! {
             ident_head  | IDENT_NAME_ST,        true
             parenthesis | PARENTHESIS_ST,       true
             delimiter   | DELIMITER_ST,         true
-            singlequote | SQUOTE_STR_BSLASH_ST, true
+            singlequote | SQUOTE_STR_ST,        true
             doublequote | DQUOTE_STR_ST,        true
             antiquote   | AQUOTE_STR_ST,        true
             zero        | NUM_ZERO_HEAD_ST,     true
@@ -110,8 +110,8 @@ lazy_static! {
             ident_head | IDENT_NAME_ST, false
         },
 
-        SQUOTE_STR_BSLASH_ST => {
-            anybutbslashsq | SQUOTE_STR_BSLASH_ST,       false
+        SQUOTE_STR_ST => {
+            anybutbslashsq | SQUOTE_STR_ST,        false
             bslash         | SQUOTE_STR_BSLASH_ST, false
             singlequote    | SQUOTE_END_ST,       false
         },
@@ -121,7 +121,7 @@ lazy_static! {
             parenthesis | PARENTHESIS_ST, true
         },
         SQUOTE_STR_BSLASH_ST => {
-            any | SQUOTE_STR_BSLASH_ST, false
+            any | SQUOTE_STR_ST, false
         },
 
         DQUOTE_STR_ST => {
@@ -163,7 +163,7 @@ lazy_static! {
             delimiter   | DELIMITER_ST,      true
             ident_head  | IDENT_NAME_ST,      true
 
-            singlequote | SQUOTE_STR_BSLASH_ST, true
+            singlequote | SQUOTE_STR_ST,        true
             doublequote | DQUOTE_STR_ST, true
             antiquote   | AQUOTE_STR_ST, true
 
@@ -247,7 +247,7 @@ lazy_static! {
             delimiter   | DELIMITER_ST,      true
             parenthesis | PARENTHESIS_ST,    true
 
-            singlequote | SQUOTE_STR_BSLASH_ST, true
+            singlequote | SQUOTE_STR_ST,        true
             doublequote | DQUOTE_STR_ST, true
             antiquote   | AQUOTE_STR_ST, true
 
@@ -264,12 +264,13 @@ lazy_static! {
         }
     };
 
-    static ref RECOGNIZER: TokenRecognizer = token_recognizer![ 2 |
+    pub(crate) static ref RECOGNIZER: TokenRecognizer = token_recognizer![ 2 |
         id        => "[[:alpha:]_][[:alnum:]_]",
 
         // Lit
-        lit_int   => r"[+|-]?(([0-9]+)|(0x[0-9a-f]+))",
-        lit_float => r"[+|-]?([0-9]+\.[0-9])",
+        lit_int   => r"[+-]?(([0-9]+)|(0x[0-9a-f]+))",
+        lit_float => r"[+-]?([0-9]+\.[0-9])",
+        sqstr     => r#"'"#,
         dqstr     => r#"""#,
         aqstr     => r#"`"#,
         lit_regex => r#"/.*/"#,
@@ -278,7 +279,7 @@ lazy_static! {
         slash_line_comment  => r"//",
 
         // space
-        sp      => "[[:BLANK_ST:]]+",
+        sp      => "[[:blank:]]+",
         newline => r#"\n\r?"#,
 
         // Bracket
@@ -306,7 +307,7 @@ lazy_static! {
 
         // Binary Operation
         sub    => "-",
-        add    => r"\+[^\+]",
+        add    => r"\+",
         mul    => r"\*",
         div    => "/",
         dot    => r"\.",
@@ -337,20 +338,20 @@ pub fn tokenize(source: &SrcFileInfo) -> TokenizeResult {
 mod tests {
     use std::path::PathBuf;
 
-    use m6lexerkit::SrcFileInfo;
+    use m6lexerkit::{SrcFileInfo, TokenList};
 
     use super::tokenize;
-    use crate::{display_pure_tok, trim_tokens};
+    use crate::trim_tokens;
 
     #[test]
     fn test_tokenize2() {
         let path = PathBuf::from("./examples/exp0.js");
-        let srcfile = SrcFileInfo::new(path).unwrap();
+        let srcfile = SrcFileInfo::new(&path).unwrap();
 
         match tokenize(&srcfile) {
             Ok(tokens) => {
                 let tokens = trim_tokens(&tokens[..]);
-                display_pure_tok(&tokens[..]);
+                print!("{}", TokenList(&tokens));
             },
             Err(err) => println!("{}", err),
         }
@@ -1,3 +1,5 @@
+#![allow(clippy::four_forward_slashes)]
+
 extern crate proc_macro;
 
 use proc_macro::TokenStream;
@@ -22,9 +24,16 @@ impl Parse for MakeCharMatcherRules {
         while !input.is_empty() {
             let name = input.parse()?;
                 input.parse::<Token!(=>)>()?;
-            let patstr = input.parse()?;
+            let patstr: LitStr = input.parse()?;
                 input.parse::<Token!(|)>()?;
-            let matcher_t = input.parse()?;
+            let matcher_t: Ident = input.parse()?;
+
+            if matcher_t == "n" && patstr.value().is_empty() {
+                return Err(syn::Error::new(
+                    patstr.span(),
+                    "simple char matcher needs a char",
+                ));
+            }
 
             if !input.is_empty() {
                 input.parse::<Token!(,)>()?;
@@ -68,7 +77,7 @@ pub fn make_char_matcher_rules(input: TokenStream) -> TokenStream {
             Span::call_site(),
         );
 
-        if matcher_t.to_string() == "r" {
+        if matcher_t == "r" {
             token_stream.extend(quote! {
                 #[inline]
                 pub fn #matcher_fn_name(c: char) -> bool {
@@ -109,10 +118,21 @@ pub fn make_char_matcher_rules(input: TokenStream) -> TokenStream {
 ////////////////////////////////////////////////////////////////////////////////
 //// TokenMatcher
 
+enum TokenMatcherRule {
+    /// `name => "patstr"` or `name => "patstr" / "trailing"`,
+    /// any of the rules but `Bare` may be followed by `|> value_fn`
+    Pattern(LitStr, Option<LitStr>),
+    /// `name => @path::to::matcher_fn` or
+    /// `name => @path::to::matcher_factory(args..)`
+    Path(syn::Path, Option<proc_macro2::TokenStream>),
+    /// `name`, use `name_m` in scope
+    Bare,
+}
+
 #[allow(unused)]
 struct TokenMatcherRules {
-    // ident, patstr
-    rules: Vec<(Ident, Option<LitStr>)>,
+    /// name, rule, value transformer (`rule |> path::to::fn`)
+    rules: Vec<(Ident, TokenMatcherRule, Option<syn::Path>)>,
 }
 
 impl Parse for TokenMatcherRules {
@@ -120,16 +140,54 @@ impl Parse for TokenMatcherRules {
         let mut rules = vec![];
 
         while !input.is_empty() {
-            let name = input.parse()?;
+            let name: Ident = input.parse()?;
 
-            if input.peek(Token![=>]) {
+            let rule = if input.peek(Token![=>]) {
                 input.parse::<Token![=>]>()?;
-                let patstr = input.parse::<LitStr>()?;
-                rules.push((name, Some(patstr)))
+
+                if input.peek(Token![@]) {
+                    input.parse::<Token![@]>()?;
+                    let path = input.parse::<syn::Path>()?;
+                    let args = if input.peek(syn::token::Paren) {
+                        let content;
+                        syn::parenthesized!(content in input);
+                        Some(content.parse()?)
+                    } else {
+                        None
+                    };
+                    TokenMatcherRule::Path(path, args)
+                }
+                else {
+                    let patstr = input.parse::<LitStr>()?;
+                    let trailing = if input.peek(Token![/]) {
+                        input.parse::<Token![/]>()?;
+                        Some(input.parse::<LitStr>()?)
+                    } else {
+                        None
+                    };
+                    TokenMatcherRule::Pattern(patstr, trailing)
+                }
             }
             else {
-                rules.push((name, None))
-            }
+                TokenMatcherRule::Bare
+            };
+
+            let transform = if input.peek(Token![|]) && input.peek2(Token![>]) {
+                input.parse::<Token![|]>()?;
+                input.parse::<Token![>]>()?;
+
+                if let TokenMatcherRule::Bare = rule {
+                    return Err(syn::Error::new(
+                        name.span(),
+                        "value transformer needs a pattern or an @matcher",
+                    ));
+                }
+                Some(input.parse::<syn::Path>()?)
+            } else {
+                None
+            };
+
+            rules.push((name, rule, transform));
 
             if !input.is_empty() {
                 input.parse::<Token![,]>()?;
@@ -153,32 +211,89 @@ pub fn make_token_matcher_rules(input: TokenStream) -> TokenStream {
     };
 
     let mut matchers_ts = quote! {};
+    let mut variants_ts = quote! {};
+    let mut as_str_ts = quote! {};
+    let mut from_str_ts = quote! {};
+    let mut names_ts = quote! {};
+    let mut name_consts_ts = quote! {};
 
-    for (name, patstr_opt) in rules {
+    for (name, rule, transform) in rules {
+        let variant = Ident::new(&camel_case(&name.to_string()), name.span());
+
+        variants_ts.extend(quote! { #variant, });
+        as_str_ts.extend(quote! { Self::#variant => stringify!(#name), });
+        from_str_ts.extend(quote! { stringify!(#name) => Ok(Self::#variant), });
+
+        let name_const = Ident::new(&name.to_string().to_uppercase(), name.span());
+
+        names_ts.extend(quote! { stringify!(#name), });
+        name_consts_ts.extend(quote! {
+            pub const #name_const: &str = stringify!(#name);
+        });
 
         let matcher_fn_name = Ident::new(
             &format!("{}_m", name.to_string().to_lowercase()),
             Span::call_site(),
         );
 
-        if let Some(patstr) = patstr_opt {
-            let matcher_reg_name = Ident::new(
-                &format!("{}_REG", name.to_string().to_uppercase()),
-                Span::call_site(),
-            );
-            let adjust_patstr =
-                LitStr::new(&format!("^({})", patstr.value()), Span::call_site());
+        let transform_ts = match transform {
+            Some(f) => quote! {
+                .map(|res| res.map(|tok| {
+                    let value = #f(&tok.value_string());
+                    tok.mapval(&value)
+                }))
+            },
+            None => quote! {},
+        };
 
-            token_stream.extend(quote! {
-                pub fn #matcher_fn_name(s: &str, from: usize) -> Option<TokenMatchResult> {
-                    m6lexerkit::lazy_static::lazy_static! {
-                        static ref #matcher_reg_name: m6lexerkit::TokenMatcher
-                            = m6lexerkit::TokenMatcher::new(#adjust_patstr, stringify!(#name));
+        match rule {
+            TokenMatcherRule::Pattern(patstr, trailing) => {
+                let matcher_reg_name = Ident::new(
+                    &format!("{}_REG", name.to_string().to_uppercase()),
+                    Span::call_site(),
+                );
+                let adjust_patstr = LitStr::new(
+                    &format!("^({})", patstr.value()),
+                    Span::call_site()
+                );
+
+                let matcher_new = match trailing {
+                    Some(trailing) => quote! {
+                        m6lexerkit::TokenMatcher::with_trailing(
+                            #adjust_patstr, #trailing, stringify!(#name)
+                        )
+                    },
+                    None => quote! {
+                        m6lexerkit::TokenMatcher::new(#adjust_patstr, stringify!(#name))
+                    },
+                };
+
+                token_stream.extend(quote! {
+                    pub fn #matcher_fn_name(s: &str, from: usize) -> Option<TokenMatchResult> {
+                        m6lexerkit::lazy_static::lazy_static! {
+                            static ref #matcher_reg_name: m6lexerkit::TokenMatcher
+                                = #matcher_new;
+                        }
+
+                        #matcher_reg_name.fetch_tok(s, from)#transform_ts
                     }
-
-                    #matcher_reg_name.fetch_tok(s, from)
-                }
-            });
+                });
+            }
+            TokenMatcherRule::Path(path, args) => {
+                let matcher = match args {
+                    Some(args) => quote! { (#path(#args)) },
+                    None => quote! { #path },
+                };
+
+                token_stream.extend(quote! {
+                    pub fn #matcher_fn_name(s: &str, from: usize) -> Option<TokenMatchResult> {
+                        #matcher(s, from).map(|res| {
+                            res.map(|tok| tok.rename(stringify!(#name)))
+                        })#transform_ts
+                    }
+                });
+            }
+            TokenMatcherRule::Bare => (),
         }
 
         matchers_ts.extend(quote! { #matcher_fn_name as m6lexerkit::FnMatcher, });
@@ -188,7 +303,114 @@ pub fn make_token_matcher_rules(input: TokenStream) -> TokenStream {
         m6lexerkit::lazy_static::lazy_static! {
             pub static ref MATCHERS: Vec<m6lexerkit::FnMatcher> = vec![#matchers_ts];
         }
+
+        /// Token names declared by the rules, in declaration order
+        pub const TOKEN_NAMES: &[&str] = &[#names_ts];
+
+        /// Token name constants, `names::LIT_INT == "lit_int"`
+        pub mod names {
+            #name_consts_ts
+        }
+
+        /// Token names declared by the rules
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum TokName {
+            #variants_ts
+        }
+
+        impl TokName {
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    #as_str_ts
+                }
+            }
+        }
+
+        impl std::convert::TryFrom<m6lexerkit::Token> for TokName {
+            type Error = m6lexerkit::Token;
+
+            fn try_from(tok: m6lexerkit::Token) -> Result<Self, Self::Error> {
+                match tok.name_string().as_str() {
+                    #from_str_ts
+                    _ => Err(tok),
+                }
+            }
+        }
     });
 
     TokenStream::from(token_stream)
 }
+
+
+////////////////////////////////////////////////////////////////////////////////
+//// CheckDupStates
+
+/// Used by `lexdfamap!`: `compile_error!` on a state key given twice,
+/// the keys (comma separated expressions) are compared by their tokens
+#[doc(hidden)]
+#[proc_macro]
+pub fn check_dup_states(input: TokenStream) -> TokenStream {
+    let input = proc_macro2::TokenStream::from(input);
+    let mut keys: Vec<String> = vec![];
+    let mut key = proc_macro2::TokenStream::new();
+    let mut trees = input.into_iter().peekable();
+
+    while let Some(tree) = trees.next() {
+        let is_comma = matches!(
+            &tree,
+            proc_macro2::TokenTree::Punct(p) if p.as_char() == ','
+        );
+
+        if !is_comma {
+            key.extend(std::iter::once(tree));
+        }
+
+        if is_comma || trees.peek().is_none() {
+            let key = std::mem::take(&mut key);
+            let key_str = key.to_string();
+
+            if keys.contains(&key_str) {
+                let span = first_span(key).unwrap_or_else(Span::call_site);
+
+                return syn::Error::new(
+                    span,
+                    format!("lexdfamap!: duplicated state key `{key_str}`"),
+                )
+                .to_compile_error()
+                .into();
+            }
+
+            keys.push(key_str);
+        }
+    }
+
+    TokenStream::new()
+}
+
+
+/// Span of the first token, looking into the invisible group wrapping an
+/// `$x:expr` passed by `macro_rules!`
+fn first_span(tokens: proc_macro2::TokenStream) -> Option<Span> {
+    match tokens.into_iter().next()? {
+        proc_macro2::TokenTree::Group(group)
+            if group.delimiter() == proc_macro2::Delimiter::None =>
+        {
+            first_span(group.stream())
+        }
+        tree => Some(tree.span()),
+    }
+}
+
+/// snake_case -> CamelCase
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
@@ -1,17 +1,27 @@
+#![allow(clippy::four_forward_slashes)]
+
 use std::{
-    cell::RefCell,
-    cmp::min,
+    cell::{OnceCell, RefCell},
+    cmp::{max, min},
     collections::HashMap,
-    error::Error,
-    fmt, fs,
+    fmt,
     hash::Hash,
+    io,
+    ops::Range,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
 
 pub use concat_idents::concat_idents as concat_idents2;
 pub use lazy_static;
 pub use proc_macros::{make_char_matcher_rules, make_token_matcher_rules};
+#[doc(hidden)]
+pub use proc_macros::check_dup_states as __check_dup_states;
 pub use regex::Regex;
+use regex::RegexSet;
 use string_interner::{symbol::DefaultSymbol, StringInterner};
 
 thread_local! {
@@ -23,6 +33,15 @@ thread_local! {
 #[derive(Clone, Copy)]
 pub struct Symbol(pub DefaultSymbol);
 
+impl Symbol {
+    /// `str2sym`, precompute the symbols (keywords ...) once and compare
+    /// tokens by id after
+    #[inline]
+    pub fn from_str_interned(s: &str) -> Self {
+        str2sym(s)
+    }
+}
+
 impl std::fmt::Debug for Symbol {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", sym2str(*self))
@@ -45,7 +64,7 @@ impl Eq for Symbol {}
 
 impl PartialOrd for Symbol {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.0.partial_cmp(&other.0)
+        Some(self.cmp(other))
     }
 }
 
@@ -60,6 +79,22 @@ impl Ord for Symbol {
 ////////////////////////////////////////////////////////////////////////////////
 //// Source File Structure
 
+/// Default `SrcFileInfo::tab_width`
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// Line ending of a source, see `SrcFileInfo::detect_newline_style`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NewlineStyle {
+    /// `\n`, also for a source without any line break
+    Lf,
+    /// `\r\n`
+    CrLf,
+    /// lone `\r`
+    Cr,
+    /// more than one of them
+    Mixed,
+}
+
 /// SrcFileInfo
 #[allow(dead_code)]
 #[derive(PartialEq, Eq, Clone)]
@@ -73,14 +108,25 @@ pub struct SrcFileInfo {
     blines: Vec<usize>, // bytes offset
 
     srcstr: String,
+    /// chars count of `srcstr`
+    char_len: usize,
+
+    /// bytes offset of this source inside the including (parent) source
+    base_offset: usize,
+
+    /// columns per tab stop for the visual column
+    tab_width: usize,
 }
 
 impl SrcFileInfo {
-    pub fn new<P: AsRef<Path>>(path: &P) -> Result<Self, Box<dyn Error>> {
-        let srcstr = fs::read_to_string(&path)?;
+    /// Read the source file
+    pub fn new<P: AsRef<Path>>(
+        path: &P,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let srcstr = std::fs::read_to_string(path)?;
         let path = path.as_ref().to_owned();
 
-        let lines = Self::build_lines(&srcstr);
+        let (lines, char_len) = Self::build_lines(&srcstr);
         let blines = Self::build_blines(&srcstr);
 
         Ok(Self {
@@ -88,11 +134,27 @@ impl SrcFileInfo {
             lines,
             blines,
             srcstr,
+            char_len,
+            base_offset: 0,
+            tab_width: DEFAULT_TAB_WIDTH,
         })
     }
 
+    /// Read the source from `reader` (stdin, socket ...), `path_label` stands
+    /// for the path in diagnostics
+    pub fn from_reader<R: std::io::Read>(
+        path_label: &str,
+        mut reader: R,
+    ) -> std::io::Result<Self> {
+        let mut srcstr = String::new();
+        reader.read_to_string(&mut srcstr)?;
+
+        Ok(Self::from_str_named(path_label, srcstr))
+    }
+
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(srcstr: String) -> Self {
-        let lines = Self::build_lines(&srcstr);
+        let (lines, char_len) = Self::build_lines(&srcstr);
         let blines = Self::build_blines(&srcstr);
 
         Self {
@@ -100,10 +162,49 @@ impl SrcFileInfo {
             lines,
             blines,
             srcstr,
+            char_len,
+            base_offset: 0,
+            tab_width: DEFAULT_TAB_WIDTH,
+        }
+    }
+
+    /// `from_str` with `label` standing for the path in diagnostics
+    /// (e.g. `<repl>`)
+    pub fn from_str_named(label: &str, srcstr: String) -> Self {
+        Self {
+            path: PathBuf::from(label),
+            ..Self::from_str(srcstr)
         }
     }
 
-    fn build_lines(srcstr: &str) -> Vec<usize> {
+    /// Mark this source as a fragment starting at bytes `base` of its parent,
+    /// shift its tokens by `base_offset()` to get positions in the parent.
+    pub fn with_base_offset(self, base: usize) -> Self {
+        Self {
+            base_offset: base,
+            ..self
+        }
+    }
+
+    pub fn base_offset(&self) -> usize {
+        self.base_offset
+    }
+
+    /// Columns per tab stop used by `boffset2visualcol` and the error caret,
+    /// 0 is clamped to 1
+    pub fn with_tab_width(self, tab_width: usize) -> Self {
+        Self {
+            tab_width: max(tab_width, 1),
+            ..self
+        }
+    }
+
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+
+    /// (lines, total chars)
+    fn build_lines(srcstr: &str) -> (Vec<usize>, usize) {
         let mut lines = vec![0];
         let mut total = 0usize;
 
@@ -115,7 +216,7 @@ impl SrcFileInfo {
             }
         }
 
-        lines
+        (lines, total)
     }
 
     fn build_blines(srcstr: &str) -> Vec<usize> {
@@ -137,8 +238,18 @@ impl SrcFileInfo {
         &self.srcstr
     }
 
+    #[inline]
+    pub fn byte_len(&self) -> usize {
+        self.srcstr.len()
+    }
+
+    #[inline]
+    pub fn char_len(&self) -> usize {
+        self.char_len
+    }
+
     pub fn get_path(&self) -> &Path {
-        &self.path.as_path()
+        self.path.as_path()
     }
 
     pub fn offset2srcloc(&self, offset: usize) -> SrcLoc {
@@ -158,8 +269,11 @@ impl SrcFileInfo {
         }
     }
 
-    /// bytes offset
+    /// bytes offset, an offset past the end or inside a char is taken as
+    /// the char boundary before it
     pub fn boffset2srcloc(&self, offset: usize) -> SrcLoc {
+        let offset = self.clamp_boffset(offset);
+
         match self.blines.binary_search(&offset) {
             Ok(found) => {
                 SrcLoc {
@@ -179,29 +293,183 @@ impl SrcFileInfo {
         }
     }
 
+    /// The char boundary at or before bytes `offset` in the source
+    fn clamp_boffset(&self, offset: usize) -> usize {
+        let mut offset = min(offset, self.srcstr.len());
+
+        while !self.srcstr.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        offset
+    }
+
+    /// Visual column (1-based) of bytes offset with tabs expanded to
+    /// `tab_width`, while `boffset2srcloc` gives the char column,
+    /// the offset is clamped the same way
+    pub fn boffset2visualcol(&self, offset: usize) -> usize {
+        let offset = self.clamp_boffset(offset);
+        let SrcLoc { ln, col: _ } = self.boffset2srcloc(offset);
+        let line_start = self.blines[ln - 1];
+
+        self.visual_width(&self.srcstr[line_start..offset]) + 1
+    }
+
+    /// Column (1-based) of bytes offset counted in grapheme clusters,
+    /// a base char with its combining marks takes one column
+    #[cfg(feature = "unicode-segmentation")]
+    pub fn boffset2graphemecol(&self, offset: usize) -> usize {
+        use unicode_segmentation::UnicodeSegmentation;
+
+        let offset = self.clamp_boffset(offset);
+        let SrcLoc { ln, col: _ } = self.boffset2srcloc(offset);
+        let line_start = self.blines[ln - 1];
+
+        self.srcstr[line_start..offset].graphemes(true).count() + 1
+    }
+
+    /// Columns taken by `s` from the line start
+    fn visual_width(&self, s: &str) -> usize {
+        s.chars().fold(0, |width, c| self.visual_advance(width, c))
+    }
+
+    /// Column after `c` put at the column `width` (0-based)
+    fn visual_advance(&self, width: usize, c: char) -> usize {
+        if c == '\t' {
+            width + self.tab_width - width % self.tab_width
+        } else {
+            width + 1
+        }
+    }
+
+    /// `line` with the tabs replaced by the spaces of their `visual_width`
+    fn expand_tabs(&self, line: &str) -> String {
+        let mut expanded = String::with_capacity(line.len());
+        let mut width = 0;
+
+        for c in line.chars() {
+            let next = self.visual_advance(width, c);
+
+            if c == '\t' {
+                expanded.push_str(&" ".repeat(next - width));
+            } else {
+                expanded.push(c);
+            }
+
+            width = next;
+        }
+
+        expanded
+    }
+
+    /// Pair each token with the `SrcLoc` of its span start (bytes offset)
+    pub fn locate_tokens<'a>(
+        &'a self,
+        tokens: &'a [Token],
+    ) -> impl Iterator<Item = (Token, SrcLoc)> + 'a {
+        tokens
+            .iter()
+            .map(|tok| (*tok, self.boffset2srcloc(tok.span.from)))
+    }
+
+    /// `SrcLoc` of span start and end (bytes offset)
+    pub fn span2srcspan(&self, span: Span) -> (SrcLoc, SrcLoc) {
+        (self.boffset2srcloc(span.from), self.boffset2srcloc(span.end))
+    }
+
     pub fn linestr(&self, cur: usize) -> Option<&str> {
         let SrcLoc { ln, col: _ } = self.boffset2srcloc(cur);
 
-        if ln - 1 >= self.blines.len() {
-            None
+        self.slice_lines(ln, ln)
+    }
+
+    /// Text of `span`, `None` if it's out of range or not on char boundaries
+    pub fn slice(&self, span: Span) -> Option<&str> {
+        self.srcstr.get(span.from..span.end)
+    }
+
+    /// Text of lines `from_ln..=to_ln` (1-based, as `SrcLoc::ln`)
+    /// including the last line break
+    pub fn slice_lines(&self, from_ln: usize, to_ln: usize) -> Option<&str> {
+        if from_ln == 0 || from_ln > to_ln || to_ln > self.blines.len() {
+            return None;
+        }
+
+        let start = self.blines[from_ln - 1];
+
+        if to_ln == self.blines.len() {
+            self.srcstr.get(start..)
         } else {
-            let start = self.blines[ln - 1];
-            let end;
-            if ln == self.blines.len() {
-                self.srcstr.get(start..)
-            } else {
-                end = self.blines[ln];
-                self.srcstr.get(start..end)
+            self.srcstr.get(start..self.blines[to_ln])
+        }
+    }
+
+    pub fn detect_newline_style(&self) -> NewlineStyle {
+        let (mut lf, mut crlf, mut cr) = (0usize, 0usize, 0usize);
+        let mut bytes = self.srcstr.bytes().peekable();
+
+        while let Some(b) = bytes.next() {
+            match b {
+                b'\n' => lf += 1,
+                b'\r' if bytes.peek() == Some(&b'\n') => {
+                    bytes.next();
+                    crlf += 1;
+                }
+                b'\r' => cr += 1,
+                _ => (),
             }
         }
+
+        match (lf > 0, crlf > 0, cr > 0) {
+            (_, false, false) => NewlineStyle::Lf,
+            (false, true, false) => NewlineStyle::CrLf,
+            (false, false, true) => NewlineStyle::Cr,
+            _ => NewlineStyle::Mixed,
+        }
+    }
+
+    /// `(ln, span, text)` of each line (1-based `ln`, bytes `span`),
+    /// the span and text include the line break if any
+    pub fn lines_with_ranges(&self) -> impl Iterator<Item = (usize, Span, &str)> {
+        let ends = self.blines[1..]
+            .iter()
+            .copied()
+            .chain(std::iter::once(self.srcstr.len()));
+
+        self.blines
+            .iter()
+            .copied()
+            .zip(ends)
+            .enumerate()
+            .filter(|(_, (from, end))| from < end)
+            .map(|(i, (from, end))| {
+                (i + 1, Span { from, end }, &self.srcstr[from..end])
+            })
+    }
+
+    /// Separators are both `/` and `\\` (`Path` splits DOS paths only on Windows)
+    fn split_path(&self) -> (String, String) {
+        let path = self.path.to_string_lossy();
+        let path = match path.trim_end_matches(['/', '\\']) {
+            "" => &path[..min(path.len(), 1)], // root or empty
+            trimmed => trimmed,
+        };
+
+        match path.rfind(['/', '\\']) {
+            Some(0) => (path[..1].to_owned(), path[1..].to_owned()),
+            Some(i) => (path[..i].to_owned(), path[i + 1..].to_owned()),
+            None => (String::new(), path.to_owned()),
+        }
     }
 
+    /// Last path component, `""` if there is none (root or `from_str` source)
     pub fn filename(&self) -> String {
-        self.path.file_name().unwrap().to_string_lossy().to_string()
+        self.split_path().1
     }
 
+    /// Path without the last component, `""` if there is none
     pub fn dirname(&self) -> String {
-        self.path.parent().unwrap().to_string_lossy().to_string()
+        self.split_path().0
     }
 }
 
@@ -213,7 +481,7 @@ impl fmt::Debug for SrcFileInfo {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct SrcLoc {
     pub ln: usize,
     pub col: usize,
@@ -240,6 +508,12 @@ impl fmt::Display for SrcLoc {
     }
 }
 
+impl PartialOrd for SrcLoc {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Ord for SrcLoc {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         if self.ln == other.ln {
@@ -251,6 +525,67 @@ impl Ord for SrcLoc {
 }
 
 
+/// Several sources concatenated into one virtual bytes offset space
+#[derive(Default, Debug)]
+pub struct SourceMap {
+    files: Vec<SrcFileInfo>,
+    /// global bytes offset where each file starts
+    starts: Vec<usize>,
+    total: usize,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `srcfile`, return its global base offset
+    pub fn add(&mut self, srcfile: SrcFileInfo) -> usize {
+        let base = self.total;
+
+        self.total += srcfile.get_srcstr().len();
+        self.starts.push(base);
+        self.files.push(srcfile);
+
+        base
+    }
+
+    pub fn files(&self) -> &[SrcFileInfo] {
+        &self.files
+    }
+
+    /// Total bytes length
+    pub fn len(&self) -> usize {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Resolve a global bytes offset to its file and location in it,
+    /// offset on a boundary belongs to the following file.
+    ///
+    /// None if the map is empty or `global_offset` is beyond its end.
+    pub fn lookup(
+        &self,
+        global_offset: usize,
+    ) -> Option<(&SrcFileInfo, SrcLoc)> {
+        if self.files.is_empty() || global_offset > self.total {
+            return None;
+        }
+
+        let idx = self.starts.partition_point(|&start| start <= global_offset) - 1;
+        let srcfile = &self.files[idx];
+
+        Some((
+            srcfile,
+            srcfile.boffset2srcloc(global_offset - self.starts[idx]),
+        ))
+    }
+}
+
+
 #[derive(
     Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Default, Debug,
 )]
@@ -265,9 +600,79 @@ impl Span {
         self.end - self.from
     }
 
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.from == self.end
+    }
+
     pub fn chars_count(&self, source: &str) -> usize {
         source[self.from..self.end].chars().count()
     }
+
+    /// Move the span by `delta` bytes, panic if it would go below zero,
+    /// see `checked_shift` for the offsets of the caller data
+    pub fn shift(self, delta: isize) -> Span {
+        self.checked_shift(delta)
+            .unwrap_or_else(|| panic!("{self:?} shift {delta} underflow"))
+    }
+
+    /// `shift`, None if it would go below zero (or overflow)
+    pub fn checked_shift(self, delta: isize) -> Option<Span> {
+        Some(Span {
+            from: self.from.checked_add_signed(delta)?,
+            end: self.end.checked_add_signed(delta)?,
+        })
+    }
+
+    /// Share at least one byte
+    pub fn overlaps(&self, other: &Span) -> bool {
+        self.from < other.end && other.from < self.end
+    }
+
+    /// One ends where the other starts (either order)
+    pub fn adjacent(&self, other: &Span) -> bool {
+        self.end == other.from || other.end == self.from
+    }
+
+    /// Span between the two (either order),
+    /// `None` if they overlap or are adjacent
+    pub fn gap(&self, other: &Span) -> Option<Span> {
+        let (first, second) = if self.from <= other.from {
+            (self, other)
+        } else {
+            (other, self)
+        };
+
+        if first.end < second.from {
+            Some(Span {
+                from: first.end,
+                end: second.from,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// `&source[self.from..self.end]`
+    #[inline]
+    pub fn index_into<'a>(&self, source: &'a str) -> &'a str {
+        &source[Range::from(*self)]
+    }
+}
+
+impl From<Span> for Range<usize> {
+    fn from(span: Span) -> Self {
+        span.from..span.end
+    }
+}
+
+impl From<Range<usize>> for Span {
+    fn from(range: Range<usize>) -> Self {
+        Span {
+            from: range.start,
+            end: range.end,
+        }
+    }
 }
 
 
@@ -275,30 +680,129 @@ impl Span {
 ////////////////////////////////////////////////////////////////////////////////
 //// Token
 
+/// Token flag reserved for the tokenizer: the matched span is consumed
+/// but no token is emitted, see `Token::skip`
+pub const FLAG_SKIP: u32 = 1 << 31;
+
+/// Token flag reserved for the tokenizer: the token is dropped (as if its
+/// matcher didn't match) unless it starts a line, see `prelude::at_line_start`
+pub const FLAG_LINE_START: u32 = 1 << 30;
+
+/// Lexical token
+///
+/// Length notions:
+///
+/// - `span_len`: bytes length of the source slice (e.g. with string quotes)
+/// - `value_bytes_len`: bytes length of the interned value
+/// - `chars_len`: chars count of the interned value
 #[derive(Clone, Copy)]
 pub struct Token {
     pub name: Symbol,
     pub value: Symbol,
     pub span: Span,
+    /// matcher specific attributes (bitset), see `prelude::FLAG_*`
+    /// and `FLAG_SKIP`, `FLAG_LINE_START`
+    pub flags: u32,
+    /// chars offset of `span.from`, memoized by the tokenizers
+    ///
+    /// The memos are only valid for the tokenized source and the span as
+    /// tokenized, they are cleared by the methods changing the span
+    /// (`with_span`, `shift_span`), build a token of another span with
+    /// them or with `Token::new`.
+    char_from: Option<usize>,
+    /// chars count of `span`, memoized by the tokenizers
+    char_len: Option<usize>,
 }
 
 impl Token {
-    pub fn eof() -> Self {
+    /// Token without flags and memo, the way the matchers build it
+    pub fn new(name: &str, value: &str, span: Span) -> Self {
+        Self::from_parts(str2sym(name), str2sym(value), span)
+    }
+
+    /// `new` of the interned name and value, inverse of `parts`
+    pub fn from_parts(name: Symbol, value: Symbol, span: Span) -> Self {
         Self {
-            name: str2sym("eof"),
-            value: str2sym(""),
-            span: Span::default(),
+            name,
+            value,
+            span,
+            flags: 0,
+            char_from: None,
+            char_len: None,
         }
     }
 
+    pub fn eof() -> Self {
+        Self::new("eof", "", Span::default())
+    }
+
+    /// Result for a matcher dropping `span` from the output,
+    /// `tokenize` advances over it without pushing a token
+    pub fn skip(span: Span) -> Self {
+        Self::new("__skip", "", span).with_flag(FLAG_SKIP)
+    }
+
+    #[inline]
+    pub fn is_skip(&self) -> bool {
+        self.has_flag(FLAG_SKIP)
+    }
+
     pub fn name_string(&self) -> String {
         sym2str(self.name)
     }
 
+    /// Convert the name into the `TokName` enum generated by
+    /// `make_token_matcher_rules!`, so that parsers get exhaustive `match`.
+    ///
+    /// ```
+    /// use m6lexerkit::{make_token_matcher_rules, Span, TokenMatchResult};
+    ///
+    /// make_token_matcher_rules! {
+    ///     id     => "[[:alpha:]_][[:alnum:]_]*",
+    ///     lit_int => "[0-9]+"
+    /// }
+    ///
+    /// let tok = Token::new("lit_int", "1", Span::default());
+    ///
+    /// match tok.name_enum::<TokName>().unwrap() {
+    ///     TokName::Id => unreachable!(),
+    ///     TokName::LitInt => (),
+    /// }
+    /// ```
+    ///
+    /// A typo'd variant is rejected at compile time:
+    ///
+    /// ```compile_fail
+    /// use m6lexerkit::{make_token_matcher_rules, Span, TokenMatchResult};
+    ///
+    /// make_token_matcher_rules! {
+    ///     id     => "[[:alpha:]_][[:alnum:]_]*",
+    ///     lit_int => "[0-9]+"
+    /// }
+    ///
+    /// let tok = Token::new("lit_int", "1", Span::default());
+    ///
+    /// match tok.name_enum::<TokName>().unwrap() {
+    ///     TokName::Id => (),
+    ///     TokName::LitInts => (),
+    /// }
+    /// ```
+    pub fn name_enum<E: TryFrom<Token>>(&self) -> Option<E> {
+        E::try_from(*self).ok()
+    }
+
     pub fn value_string(&self) -> String {
         sym2str(self.value)
     }
 
+    /// value's bytes len
+    #[inline]
+    pub fn value_bytes_len(&self) -> usize {
+        INTERNER.with(|interner| {
+            interner.borrow().resolve(self.value.0).unwrap().len()
+        })
+    }
+
     /// value's chars len
     #[inline]
     pub fn chars_len(&self) -> usize {
@@ -316,37 +820,144 @@ impl Token {
         self.span
     }
 
-    /// value's bytes len
+    /// `name value @ (ln, col)` with the start location in `src`,
+    /// for the logs
+    pub fn display_with_loc(&self, src: &SrcFileInfo) -> String {
+        format!(
+            "{} {} @ {}",
+            self.name_string(),
+            self.value_string(),
+            src.boffset2srcloc(self.span.from)
+        )
+    }
+
+    /// `(name, value, span.from, span.end)`
+    pub fn as_tuple(&self) -> (String, String, usize, usize) {
+        (
+            self.name_string(),
+            self.value_string(),
+            self.span.from,
+            self.span.end,
+        )
+    }
+
+    /// `(name, value, span)` unresolved
+    pub fn parts(&self) -> (Symbol, Symbol, Span) {
+        (self.name, self.value, self.span)
+    }
+
+    /// span's bytes len
     #[inline]
     pub fn span_len(&self) -> usize {
         self.span().len()
     }
 
+    /// Memoized chars offset of `span.from`, None if not from a tokenizer
+    #[inline]
+    pub fn char_from(&self) -> Option<usize> {
+        self.char_from
+    }
+
+    /// Memoized chars count of `span`, None if not from a tokenizer
+    #[inline]
+    pub fn char_len(&self) -> Option<usize> {
+        self.char_len
+    }
+
+    /// O(1) for tokenizer output, otherwise scan `source`
     #[inline]
     pub fn span_chars_count(&self, source: &str) -> usize {
-        self.span().chars_count(source)
+        self.char_len
+            .unwrap_or_else(|| self.span().chars_count(source))
+    }
+
+    /// Span without the `prefix_len`/`postfix_len` bytes delimiters
+    /// (e.g. quotes of a string token), the offsets stay in `source`.
+    ///
+    /// None if the delimiters overlap or the inner span isn't a char
+    /// boundaries range of `source`
+    pub fn inner_span(
+        &self,
+        source: &str,
+        prefix_len: usize,
+        postfix_len: usize,
+    ) -> Option<Span> {
+        let inner = Span {
+            from: self.span.from.checked_add(prefix_len)?,
+            end: self.span.end.checked_sub(postfix_len)?,
+        };
+
+        if inner.from > inner.end {
+            return None;
+        }
+
+        source.get(inner.from..inner.end).map(|_| inner)
+    }
+
+    /// Same token over `span`, the char memos are cleared
+    pub fn with_span(self, span: Span) -> Self {
+        Self {
+            span,
+            char_from: None,
+            char_len: None,
+            ..self
+        }
+    }
+
+    pub fn shift_span(self, delta: isize) -> Self {
+        Self {
+            span: self.span.shift(delta),
+            char_from: None,
+            ..self
+        }
+    }
+
+    /// `shift_span`, None if the span would go below zero
+    pub fn checked_shift_span(self, delta: isize) -> Option<Self> {
+        Some(Self {
+            span: self.span.checked_shift(delta)?,
+            char_from: None,
+            ..self
+        })
     }
 
     pub fn rename(self, name: &str) -> Self {
         Self {
             name: str2sym(name),
-            value: self.value,
-            span: self.span,
+            ..self
         }
     }
 
     pub fn mapval(self, val: &str) -> Self {
         Self {
-            name: self.name,
             value: str2sym(val),
-            span: self.span,
+            ..self
+        }
+    }
+
+    /// `rename` and `mapval` in one go, `f(name, value) -> (name, value)`
+    pub fn map(self, f: impl FnOnce(&str, &str) -> (String, String)) -> Self {
+        let (name, value) = f(&self.name_string(), &self.value_string());
+
+        self.rename(&name).mapval(&value)
+    }
+
+    pub fn with_flag(self, flag: u32) -> Self {
+        Self {
+            flags: self.flags | flag,
+            ..self
         }
     }
 
+    #[inline]
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags & flag == flag
+    }
+
     pub fn rename_by_value(self, values: &[&str]) -> Self {
-        for value in values.into_iter() {
-            if self.check_value(*value) {
-                return self.rename(*value);
+        for value in values.iter() {
+            if self.check_value(value) {
+                return self.rename(value);
             }
         }
         self
@@ -358,6 +969,38 @@ impl Token {
         })
     }
 
+    /// Case-insensitive (Unicode lowercase) `check_value`
+    pub fn check_value_ci(&self, value: &str) -> bool {
+        INTERNER.with(|internner| {
+            eq_ci(internner.borrow().resolve(self.value.0).unwrap(), value)
+        })
+    }
+
+    /// Compare after NFC normalization of both side
+    #[cfg(feature = "nfc")]
+    pub fn check_value_nfc(&self, value: &str) -> bool {
+        use unicode_normalization::UnicodeNormalization;
+
+        INTERNER.with(|internner| {
+            let internref = internner.borrow();
+            let tokv = internref.resolve(self.value.0).unwrap();
+
+            tokv.nfc().eq(value.nfc())
+        })
+    }
+
+    /// Same name as `other`, by symbol id (nothing resolved)
+    #[inline]
+    pub fn same_name(&self, other: &Token) -> bool {
+        self.name == other.name
+    }
+
+    /// Same value as `other`, by symbol id (nothing resolved)
+    #[inline]
+    pub fn same_value(&self, other: &Token) -> bool {
+        self.value == other.value
+    }
+
     pub fn check_name(&self, name: &str) -> bool {
         INTERNER.with(|internner| {
             internner.borrow().resolve(self.name.0).unwrap() == name
@@ -369,10 +1012,7 @@ impl Token {
             let internref = internner.borrow();
             let name = internref.resolve(self.name.0).unwrap();
 
-            targets
-                .into_iter()
-                .find(|&&target| target == name)
-                .is_some()
+            targets.contains(&name)
         })
     }
 
@@ -381,114 +1021,706 @@ impl Token {
             let internref = internner.borrow();
             let value = internref.resolve(self.value.0).unwrap();
 
-            targets
-                .into_iter()
-                .find(|&&target| target == value)
-                .is_some()
+            targets.contains(&value)
         })
     }
-}
 
+    /// Case-insensitive (Unicode lowercase) `check_values_in`
+    pub fn check_values_in_ci(&self, targets: &[&str]) -> bool {
+        INTERNER.with(|internner| {
+            let internref = internner.borrow();
+            let value = internref.resolve(self.value.0).unwrap();
 
-impl fmt::Display for Token {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "name: <{}>", self.name_string(),)?;
-        writeln!(f, "value: {}", self.value_string(),)?;
-        writeln!(f, "len: {}", self.chars_len())
+            targets.iter().any(|target| eq_ci(value, target))
+        })
     }
 }
 
-impl fmt::Debug for Token {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Token")
-            .field("name", &self.name_string())
+
+/// Token names treated as whitespace by default
+pub const DEFAULT_WHITESPACE_NAMES: &[&str] = &["sp", "newline"];
+
+/// Token names treated as comment by default
+pub const DEFAULT_COMMENT_NAMES: &[&str] =
+    &["sharp_line_comment", "slash_line_comment", "slash_block_comment"];
+
+type TriviaSyms = (Vec<Symbol>, Vec<Symbol>);
+
+thread_local! {
+    /// (whitespace names, comment names)
+    static TRIVIA_NAMES: RefCell<(Vec<String>, Vec<String>)> = RefCell::new((
+        DEFAULT_WHITESPACE_NAMES.iter().map(|s| s.to_string()).collect(),
+        DEFAULT_COMMENT_NAMES.iter().map(|s| s.to_string()).collect(),
+    ));
+
+    /// `TRIVIA_NAMES` interned on first use
+    static TRIVIA_SYMS: RefCell<Option<TriviaSyms>> = const { RefCell::new(None) };
+}
+
+/// Configure the token names used by `Token::is_trivia` (for this thread)
+pub fn set_trivia_names(whitespace: &[&str], comment: &[&str]) {
+    TRIVIA_NAMES.with(|trivia| {
+        *trivia.borrow_mut() = (
+            whitespace.iter().map(|s| s.to_string()).collect(),
+            comment.iter().map(|s| s.to_string()).collect(),
+        )
+    });
+    TRIVIA_SYMS.with(|syms| syms.take());
+}
+
+fn with_trivia_syms<R>(f: impl FnOnce(&TriviaSyms) -> R) -> R {
+    TRIVIA_SYMS.with(|syms| {
+        let mut syms = syms.borrow_mut();
+        let syms = syms.get_or_insert_with(|| {
+            TRIVIA_NAMES.with(|trivia| {
+                let trivia = trivia.borrow();
+                let intern = |names: &[String]| {
+                    str2syms(&names.iter().map(|s| s.as_str()).collect::<Vec<_>>())
+                };
+
+                (intern(&trivia.0), intern(&trivia.1))
+            })
+        });
+
+        f(syms)
+    })
+}
+
+impl Token {
+    pub fn is_whitespace(&self) -> bool {
+        with_trivia_syms(|trivia| trivia.0.contains(&self.name))
+    }
+
+    pub fn is_comment(&self) -> bool {
+        with_trivia_syms(|trivia| trivia.1.contains(&self.name))
+    }
+
+    /// Whitespace or comment
+    pub fn is_trivia(&self) -> bool {
+        self.is_whitespace() || self.is_comment()
+    }
+}
+
+
+/// Coarse category of a token, see `TokenClassifier`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    Literal,
+    Keyword,
+    Identifier,
+    Operator,
+    Punctuation,
+    Comment,
+    Whitespace,
+    Other,
+}
+
+/// Token names of punctuation (brackets, delimiters) by default
+pub const DEFAULT_PUNCTUATION_NAMES: &[&str] = &[
+    "lparen", "rparen", "lbracket", "rbracket", "lbrace", "rbrace",
+    "colon", "semi", "comma", "question",
+];
+
+/// Token names of operators by default
+pub const DEFAULT_OPERATOR_NAMES: &[&str] = &[
+    "assign", "inc", "dec", "not", "add", "add2", "sub", "mul", "div", "dot",
+    "ge", "le", "lt", "gt", "eq", "neq", "realeq", "nrealeq", "percent",
+    "and", "or", "double_arrow", "rarrow", "rdarrow", "single_arrow",
+];
+
+/// Map token name to `TokenKind`, the names set by `with` come first then
+/// the defaults after the common names of the examples:
+///
+/// - `lit_*`, `*str`, `number`, `float`, `char_lit`: `Literal`
+/// - `id`: `Identifier`
+/// - `DEFAULT_OPERATOR_NAMES`: `Operator`
+/// - `DEFAULT_PUNCTUATION_NAMES`: `Punctuation`
+/// - `DEFAULT_COMMENT_NAMES`, `*comment`: `Comment`
+/// - `DEFAULT_WHITESPACE_NAMES`: `Whitespace`
+#[derive(Clone, Default, Debug)]
+pub struct TokenClassifier {
+    names: HashMap<String, TokenKind>,
+}
+
+impl TokenClassifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Classify `name` as `kind` (e.g. the keywords)
+    pub fn with(mut self, name: &str, kind: TokenKind) -> Self {
+        self.names.insert(name.to_owned(), kind);
+        self
+    }
+
+    pub fn classify(&self, name: &str) -> TokenKind {
+        if let Some(kind) = self.names.get(name) {
+            return *kind;
+        }
+
+        if name.starts_with("lit_")
+            || name.ends_with("str")
+            || ["number", "float", "char_lit"].contains(&name)
+        {
+            TokenKind::Literal
+        } else if name == "id" {
+            TokenKind::Identifier
+        } else if DEFAULT_OPERATOR_NAMES.contains(&name) {
+            TokenKind::Operator
+        } else if DEFAULT_PUNCTUATION_NAMES.contains(&name) {
+            TokenKind::Punctuation
+        } else if DEFAULT_COMMENT_NAMES.contains(&name)
+            || name.ends_with("comment")
+        {
+            TokenKind::Comment
+        } else if DEFAULT_WHITESPACE_NAMES.contains(&name) {
+            TokenKind::Whitespace
+        } else {
+            TokenKind::Other
+        }
+    }
+}
+
+impl Token {
+    pub fn kind(&self, classifier: &TokenClassifier) -> TokenKind {
+        classifier.classify(&self.name_string())
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "name: <{}>", self.name_string(),)?;
+        writeln!(f, "value: {}", self.value_string(),)?;
+        writeln!(f, "len: {}", self.chars_len())
+    }
+}
+
+impl fmt::Debug for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Token")
+            .field("name", &self.name_string())
             .field("value", &self.value_string())
             .finish()
     }
 }
 
 
+/// Format a token stream: `Display` one value per line,
+/// `Debug` one `name value span` per line
+#[derive(Clone, Copy)]
+pub struct TokenList<'a>(pub &'a [Token]);
+
+impl fmt::Display for TokenList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for tok in self.0.iter() {
+            writeln!(f, "{}", tok.value_string())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for TokenList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for tok in self.0.iter() {
+            writeln!(
+                f,
+                "<{}> {:?} {}..{}",
+                tok.name_string(),
+                tok.value_string(),
+                tok.span.from,
+                tok.span.end
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+
+/// Cursor over a token stream for hand-written parsers,
+/// reading past the end gets `Token::eof()` (positioned at the end).
+#[derive(Clone, Copy)]
+pub struct TokenCursor<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> TokenCursor<'a> {
+    pub fn new(tokens: &'a [Token]) -> Self {
+        Self { tokens, pos: 0 }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    pub fn peek(&self) -> Token {
+        self.peek_nth(0)
+    }
+
+    pub fn peek_nth(&self, n: usize) -> Token {
+        let pos = self.pos.saturating_add(n);
+
+        self.tokens.get(pos).copied().unwrap_or_else(|| {
+            let end = self.tokens.last().map_or(0, |tok| tok.span.end);
+
+            Token::new("eof", "", Span { from: end, end })
+        })
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Token {
+        let tok = self.peek();
+
+        self.pos = min(self.pos + 1, self.tokens.len());
+
+        tok
+    }
+
+    /// Skip the trivia (`Token::is_trivia`) and advance past the next token,
+    /// for a stream keeping its whitespace and comments
+    pub fn next_significant(&mut self) -> Token {
+        while self.peek().is_trivia() {
+            self.pos += 1;
+        }
+
+        self.next()
+    }
+
+    /// Advance if the next token is named `name`, else return it as `Err`
+    pub fn expect_name(&mut self, name: &str) -> Result<Token, Token> {
+        let tok = self.peek();
+
+        if tok.check_name(name) {
+            Ok(self.next())
+        } else {
+            Err(tok)
+        }
+    }
+
+    /// Advance if the next token is named `name`
+    pub fn eat_name(&mut self, name: &str) -> bool {
+        self.expect_name(name).is_ok()
+    }
+}
+
+
+
 ////////////////////////////////////////////////////////////////////////////////
 //// Tokenize
 
+lazy_static::lazy_static! {
+    static ref REGEX_REGISTRY: Mutex<HashMap<String, Arc<Regex>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Compile `patstr` once per process, identical patterns share the `Regex`.
+///
+/// The registry is never evicted, it's for the static patterns of the rules
+/// (`make_token_matcher_rules!`), compile the patterns built at runtime with
+/// `Regex::new` (or `TokenMatcher::try_new`).
+pub fn compiled_regex(patstr: &str) -> Result<Arc<Regex>, regex::Error> {
+    let mut registry = REGEX_REGISTRY.lock().unwrap();
+
+    if let Some(pat) = registry.get(patstr) {
+        return Ok(pat.clone());
+    }
+
+    let pat = Arc::new(Regex::new(patstr)?);
+    registry.insert(patstr.to_owned(), pat.clone());
+
+    Ok(pat)
+}
+
+/// Ids of the static `TokenMatcher`s, index of `MATCHER_NAME_SYMS`
+static NEXT_MATCHER_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// `TokenMatcher::tok_name` interned on first match, by matcher id,
+    /// it grows with the static matchers only (like `compiled_regex`)
+    static MATCHER_NAME_SYMS: RefCell<Vec<Option<Symbol>>> =
+        const { RefCell::new(Vec::new()) };
+}
+
 pub struct TokenMatcher {
-    pat: Regex,
-    tok_name: Symbol,
+    pat: Arc<Regex>,
+    /// kept as string since `Symbol` is only valid on the interning thread,
+    /// the symbol is cached per thread by `id`
+    tok_name: String,
+    /// None for `try_new`, the name is interned on each match
+    id: Option<usize>,
+    /// span ends at group 1, the trailing context isn't consumed
+    trailing: bool,
 }
 
 impl TokenMatcher {
+    /// Matcher of a static pattern (shared by `compiled_regex`),
+    /// panic if `patstr` is malformed
     pub fn new(patstr: &str, tok_name: &str) -> Self {
+        Self::with_pat(compiled_regex(patstr).unwrap(), tok_name, false)
+    }
+
+    /// `new` for a pattern not known at compile time, neither the `Regex`
+    /// nor the name symbol is cached
+    pub fn try_new(patstr: &str, tok_name: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pat: Arc::new(Regex::new(patstr)?),
+            tok_name: tok_name.to_owned(),
+            id: None,
+            trailing: false,
+        })
+    }
+
+    fn with_pat(pat: Arc<Regex>, tok_name: &str, trailing: bool) -> Self {
         Self {
-            pat: Regex::new(patstr).unwrap(),
-            tok_name: str2sym(tok_name),
+            pat,
+            tok_name: tok_name.to_owned(),
+            id: Some(NEXT_MATCHER_ID.fetch_add(1, Ordering::Relaxed)),
+            trailing,
         }
     }
 
+    /// `patstr / trailing`: match `patstr` only if followed by `trailing`,
+    /// the consumed part is group 1 of `patstr` (e.g. `^(\+)`).
+    ///
+    /// Use `$` in `trailing` to accept end of input, e.g. `[^\+]|$`.
+    pub fn with_trailing(patstr: &str, trailing: &str, tok_name: &str) -> Self {
+        Self::with_pat(
+            compiled_regex(&format!("{patstr}(?:{trailing})")).unwrap(),
+            tok_name,
+            true,
+        )
+    }
+
+    /// `tok_name` interned once per thread (and interner)
+    fn name_sym(&self) -> Symbol {
+        let Some(id) = self.id else {
+            return str2sym(&self.tok_name);
+        };
+
+        MATCHER_NAME_SYMS.with(|syms| {
+            let mut syms = syms.borrow_mut();
+
+            if syms.len() <= id {
+                syms.resize(id + 1, None);
+            }
+
+            *syms[id].get_or_insert_with(|| str2sym(&self.tok_name))
+        })
+    }
+
     pub fn fetch_tok(
         &self,
         text: &str,
         start: usize,
     ) -> Option<TokenMatchResult> {
-        self.pat.captures(text).and_then(|cap| {
-            let bytes_len = cap.get(0).unwrap().as_str().len();
-            let mat = cap.get(1).unwrap().as_str();
-            let span = Span {
-                from: start,
-                end: start + bytes_len,
-            };
+        self.pat
+            .captures(text)
+            .map(|cap| Ok(self.cap2tok(&cap, start)))
+    }
 
-            Some(Ok(Token {
-                name: self.tok_name,
-                value: str2sym(mat),
-                span,
-            }))
-        })
+    /// `fetch_tok` also returning the matched named groups,
+    /// e.g. `(?P<major>\d+)\.(?P<minor>\d+)`
+    pub fn fetch_tok_captures(
+        &self,
+        text: &str,
+        start: usize,
+    ) -> Option<(Token, HashMap<String, String>)> {
+        let cap = self.pat.captures(text)?;
+        let named = self
+            .pat
+            .capture_names()
+            .flatten()
+            .filter_map(|name| {
+                cap.name(name)
+                    .map(|m| (name.to_owned(), m.as_str().to_owned()))
+            })
+            .collect();
+
+        Some((self.cap2tok(&cap, start), named))
+    }
+
+    fn cap2tok(&self, cap: &regex::Captures, start: usize) -> Token {
+        // pattern without group 1 (e.g. `^select`) takes the whole match
+        let valcap = if self.pat.captures_len() > 1 {
+            cap.get(1)
+        } else {
+            cap.get(0)
+        };
+        let bytes_len = if self.trailing {
+            valcap.map_or(0, |m| m.end())
+        } else {
+            cap.get(0).map_or(0, |m| m.end())
+        };
+        let mat = valcap.map_or("", |m| m.as_str());
+        let span = Span {
+            from: start,
+            end: start + bytes_len,
+        };
+
+        Token::from_parts(self.name_sym(), str2sym(mat), span)
     }
 }
 
 pub type FnMatcher = fn(&str, usize) -> Option<TokenMatchResult>;
+/// Matcher which may capture its configuration (a closure), or built at
+/// runtime (`prelude::matchers_from_rules`), see `tokenize_boxed`
+pub type BoxFnMatcher =
+    Box<dyn Fn(&str, usize) -> Option<TokenMatchResult> + Send + Sync>;
+/// Matcher that splits a lexeme into several contiguous tokens (`$foo` => `$`, `foo`)
+pub type MultiTokenMatcher = fn(&str, usize) -> Option<MultiTokenMatchResult>;
 
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum TokenizeErrorReason {
     UnrecognizedToken,
     UnrecognizedEscaped(char),
     UnexpectedPostfix,
-    ZeroLenToken
+    /// quoted string, comment ... not closed until the end of source
+    Unterminated,
+    ZeroLenToken,
+    UncoveredChar(char),
+    LimitExceeded,
+    /// matcher emitted a token not advancing the position (name of the token)
+    ZeroAdvance(Symbol),
+    /// matcher emitted a span ending past the source or inside a char,
+    /// it over/under-reports the consumed bytes (name of the token)
+    BadSpanEnd(Symbol),
+    /// matcher emitted a span advancing the position but not starting at it
+    /// (name of the token)
+    MisplacedSpan(Symbol),
+    /// start offset given to `tokenize_from` is past the source or
+    /// inside a char
+    BadStart,
 }
 
 
+#[derive(Clone)]
 pub struct TokenizeError {
     reason: TokenizeErrorReason,
     start: usize,
-    src: SrcFileInfo,
+    /// shared by the errors of one tokenize call
+    src: Arc<SrcFileInfo>,
+    /// token names for `UnrecognizedToken`, see `expected`
+    expected: Vec<Symbol>,
+    /// end (chars offset, exclusive) of the region skipped by a recovery
+    skipped_end: Option<usize>,
+}
+
+impl TokenizeError {
+    pub fn src(&self) -> &Arc<SrcFileInfo> {
+        &self.src
+    }
+
+    /// For `UnrecognizedToken` of `tokenize`: names of the tokens matched
+    /// when the offending char is replaced by another (ASCII) char,
+    /// in the matchers order
+    pub fn expected(&self) -> &[Symbol] {
+        &self.expected
+    }
+
+    /// End (chars offset, exclusive) of the erroneous span for the errors
+    /// covering more than a point, an unterminated construct runs to the end
+    /// of source
+    pub fn span_end(&self) -> Option<usize> {
+        match self.reason {
+            TokenizeErrorReason::Unterminated => Some(self.src.char_len()),
+            _ => self.skipped_end,
+        }
+    }
+}
+
+/// Make `TokenizeError`s of one tokenize call, the source is copied once
+/// on the first error and shared after
+struct ErrorSrc<'a> {
+    srcfile: &'a SrcFileInfo,
+    shared: OnceCell<Arc<SrcFileInfo>>,
+}
+
+impl<'a> ErrorSrc<'a> {
+    fn new(srcfile: &'a SrcFileInfo) -> Self {
+        Self {
+            srcfile,
+            shared: OnceCell::new(),
+        }
+    }
+
+    fn error(&self, reason: TokenizeErrorReason, start: usize) -> TokenizeError {
+        let src = self
+            .shared
+            .get_or_init(|| Arc::new(self.srcfile.clone()))
+            .clone();
+
+        TokenizeError {
+            reason,
+            start,
+            src,
+            expected: vec![],
+            skipped_end: None,
+        }
+    }
+
+    /// `error` of a failed `match_token`, with the expected tokens of
+    /// `UnrecognizedToken` probed at `bytes_pos`
+    fn match_error<S: MatcherSet + ?Sized>(
+        &self,
+        reason: TokenizeErrorReason,
+        start: usize,
+        bytes_pos: usize,
+        fn_matchers: &S,
+    ) -> TokenizeError {
+        let expected = match reason {
+            TokenizeErrorReason::UnrecognizedToken => {
+                fn_matchers.expected_names(self.srcfile.get_srcstr(), bytes_pos)
+            }
+            _ => vec![],
+        };
+
+        TokenizeError {
+            expected,
+            ..self.error(reason, start)
+        }
+    }
+}
+
+/// ASCII punctuations and a few of the other chars
+const EXPECTED_PROBES: &str = "aZ_09 \t\n!\"#$%&'()*+,-./:;<=>?@[\\]^`{|}~";
+
+/// Names of the tokens matched at `bytes_pos` if the char there were
+/// another one, probed with ASCII punctuations and a few of the other chars
+fn expected_names<M>(
+    source: &str,
+    bytes_pos: usize,
+    fn_matchers: &[M],
+) -> Vec<Symbol>
+where
+    M: Fn(&str, usize) -> Option<TokenMatchResult>,
+{
+    // enough of the following text for a closing quote or so
+    const TAIL_LEN: usize = 64;
+
+    let rest = &source[bytes_pos..];
+    let tail = match rest.chars().next() {
+        Some(c) => &rest[c.len_utf8()..],
+        None => rest,
+    };
+    let tail_end = (0..=min(TAIL_LEN, tail.len()))
+        .rev()
+        .find(|i| tail.is_char_boundary(*i))
+        .unwrap_or(0);
+    let tail = &tail[..tail_end];
+
+    let mut names: Vec<Symbol> = vec![];
+    let mut text = String::with_capacity(1 + tail.len());
+
+    for fn_matcher in fn_matchers {
+        for probe in EXPECTED_PROBES.chars() {
+            text.clear();
+            text.push(probe);
+            text.push_str(tail);
+
+            if let Some(Ok(tok)) = fn_matcher(&text, bytes_pos) {
+                if !tok.is_skip() && !names.contains(&tok.name) {
+                    names.push(tok.name);
+                }
+                break;
+            }
+        }
+    }
+
+    names
 }
 impl std::error::Error for TokenizeError {}
-impl std::fmt::Display for TokenizeError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let linestr = self.src.linestr(self.start).unwrap();
+impl TokenizeError {
+    /// `Display` with the underline in red and the location in blue,
+    /// plain text without the `ansi` feature or when stderr isn't a TTY
+    pub fn render_ansi(&self) -> String {
+        use std::io::IsTerminal;
+
+        let ansi = cfg!(feature = "ansi") && std::io::stderr().is_terminal();
+        let mut out = String::new();
+
+        self.render(&mut out, ansi).unwrap();
+
+        out
+    }
+
+    fn render(&self, f: &mut dyn fmt::Write, ansi: bool) -> fmt::Result {
+        let (red, blue, reset) = if ansi {
+            ("\x1b[31m", "\x1b[34m", "\x1b[0m")
+        } else {
+            ("", "", "")
+        };
         let loc = self.src.offset2srcloc(self.start);
-        let rem_len = linestr.len() - loc.col;
+        // last char of the span
+        let end_loc = match self.span_end() {
+            Some(end) if end > self.start + 1 => self.src.offset2srcloc(end - 1),
+            _ => loc,
+        };
+        let path = self.src.get_path().to_string_lossy();
+        let path = if path.is_empty() { "<anonymous>".into() } else { path };
 
         writeln!(f)?;
         writeln!(f)?;
 
         writeln!(f, "{:?}:", self.reason)?;
 
+        if !self.expected().is_empty() {
+            let names = self
+                .expected()
+                .iter()
+                .map(|sym| sym2str(*sym))
+                .collect::<Vec<_>>();
+
+            writeln!(f, "expected one of: {}", names.join(", "))?;
+        }
+
         writeln!(f)?;
-        writeln!(f, "{linestr}")?;
-        writeln!(
-            f,
-            "{}{}{}",
-            " ".repeat(loc.col - 1),
-            '^',
-            "-".repeat(rem_len)
-        )?;
+
+        for ln in loc.ln..=end_loc.ln {
+            let linestr = self
+                .src
+                .slice_lines(ln, ln)
+                .unwrap_or_default()
+                .trim_end_matches(['\n', '\r']);
+            let line_len = linestr.chars().count();
+            let from_col = if ln == loc.ln { loc.col } else { 1 };
+            let to_col = if ln == end_loc.ln && end_loc != loc {
+                end_loc.col
+            } else {
+                line_len
+            };
+
+            // tabs expanded to line up the underline
+            let width_upto = |col: usize| {
+                let prefix = linestr.chars().take(col).collect::<String>();
+                self.src.visual_width(&prefix)
+            };
+            let indent = width_upto(from_col - 1);
+            let underline_len = width_upto(to_col).saturating_sub(indent);
+
+            writeln!(f, "{}", self.src.expand_tabs(linestr))?;
+
+            if ln == loc.ln || to_col >= from_col {
+                writeln!(
+                    f,
+                    "{}{red}^{}{reset}",
+                    " ".repeat(indent),
+                    "-".repeat(underline_len.saturating_sub(1))
+                )?;
+            }
+        }
+
         writeln!(
             f,
-            "--> {}:{}:{}",
-            self.src.get_path().to_string_lossy(),
+            "{blue}--> {}:{}:{}{reset}",
+            path,
             loc.ln,
             loc.col
         )?;
@@ -498,6 +1730,12 @@ impl std::fmt::Display for TokenizeError {
         Ok(())
     }
 }
+
+impl std::fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.render(f, false)
+    }
+}
 impl std::fmt::Debug for TokenizeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{self}")
@@ -507,562 +1745,5355 @@ impl std::fmt::Debug for TokenizeError {
 
 pub type TokenizeResult = Result<Vec<Token>, TokenizeError>;
 pub type TokenMatchResult = Result<Token, TokenizeErrorReason>;
+pub type MultiTokenMatchResult = Result<Vec<Token>, TokenizeErrorReason>;
+
+/// Caps for `tokenize_bounded`, unbounded by default
+#[derive(Clone, Copy, Debug)]
+pub struct TokenizeLimits {
+    pub max_tokens: usize,
+    pub max_bytes: usize,
+}
+
+impl Default for TokenizeLimits {
+    fn default() -> Self {
+        Self {
+            max_tokens: usize::MAX,
+            max_bytes: usize::MAX,
+        }
+    }
+}
 
+#[inline]
 pub fn tokenize(
     srcfile: &SrcFileInfo,
     fn_matchers: &[FnMatcher],
 ) -> TokenizeResult {
-    let source = srcfile.get_srcstr();
-    let mut tokens = vec![];
-
-    if source.len() == 0 {
-        return Ok(tokens);
-    }
-
-    let mut bytes_pos = 0;
-    let mut chars_pos = 0usize;
-
-    while bytes_pos < source.len() {
-        let mut tok_matched = false;
-
-        for fn_matcher in fn_matchers.iter() {
-            if let Some(tokres) = fn_matcher(&source[bytes_pos..], bytes_pos) {
-                match tokres {
-                    Ok(tok) => {
-                        if tok.span_len() == 0 {
-                            return Err(TokenizeError {
-                                reason: TokenizeErrorReason::ZeroLenToken,
-                                start: chars_pos,
-                                src: srcfile.clone(),
-                            })
-                        }
-
-                        chars_pos += tok.span_chars_count(source);
-                        bytes_pos += tok.span_len();
+    tokenize_bounded(srcfile, fn_matchers, TokenizeLimits::default())
+}
 
-                        tokens.push(tok);
-                        tok_matched = true;
-                        break;
-                    }
-                    Err(reason) => {
-                        return Err(TokenizeError {
-                            reason,
-                            start: chars_pos,
-                            src: srcfile.clone(),
-                        });
-                    }
-                }
-            }
-        }
+/// cargo-fuzz entry (`fuzz_target!(|data: &[u8]| fuzz_tokenize(data, ..))`):
+/// tokenize `data` if it's UTF-8 and render the error, it must never panic
+#[cfg(fuzzing)]
+pub fn fuzz_tokenize(data: &[u8], fn_matchers: &[FnMatcher]) {
+    if let Ok(src) = std::str::from_utf8(data) {
+        let srcfile = SrcFileInfo::from_str(src.to_owned());
 
-        if !tok_matched {
-            return Err(TokenizeError {
-                reason: TokenizeErrorReason::UnrecognizedToken,
-                start: chars_pos,
-                src: srcfile.clone(),
-            });
+        if let Err(err) = tokenize(&srcfile, fn_matchers) {
+            let _ = err.to_string();
         }
     }
-
-    Ok(tokens)
 }
 
+/// `tokenize` failing with `LimitExceeded` once the source is longer than
+/// `limits.max_bytes` or more than `limits.max_tokens` tokens are produced
+#[inline]
+pub fn tokenize_bounded(
+    srcfile: &SrcFileInfo,
+    fn_matchers: &[FnMatcher],
+    limits: TokenizeLimits,
+) -> TokenizeResult {
+    let mut tokens = presized_tokens(srcfile);
 
+    tokenize_impl(
+        srcfile,
+        fn_matchers,
+        0..usize::MAX,
+        limits,
+        &mut tokens,
+        &mut (),
+    )?;
 
-////////////////////////////////////////////////////////////////////////////////
-//// Auxiliary
-
-pub fn sym2str(sym: Symbol) -> String {
-    INTERNER
-        .with(|interner| interner.borrow().resolve(sym.0).unwrap().to_owned())
+    Ok(tokens)
 }
 
-pub fn str2sym(s: &str) -> Symbol {
-    Symbol(INTERNER.with(|interner| interner.borrow_mut().get_or_intern(s)))
+/// `tokenize` appending to `tokens`, reuse one buffer across files to save
+/// the reallocations (`tokens` keeps the tokens pushed before an error)
+pub fn tokenize_into(
+    srcfile: &SrcFileInfo,
+    fn_matchers: &[FnMatcher],
+    tokens: &mut Vec<Token>,
+) -> Result<(), TokenizeError> {
+    tokenize_impl(
+        srcfile,
+        fn_matchers,
+        0..usize::MAX,
+        TokenizeLimits::default(),
+        tokens,
+        &mut (),
+    )
 }
 
+/// Bytes per token, about 3.75 on examples/es6 app.js
+const AVG_TOKEN_BYTES: usize = 4;
 
+fn presized_tokens(srcfile: &SrcFileInfo) -> Vec<Token> {
+    Vec::with_capacity(srcfile.byte_len() / AVG_TOKEN_BYTES)
+}
 
-pub mod prelude {
-    use std::collections::HashSet;
+/// `tokenize` also returning, for each token, the index of the matcher
+/// (in `fn_matchers`) which produced it
+pub fn tokenize_traced(
+    srcfile: &SrcFileInfo,
+    fn_matchers: &[FnMatcher],
+) -> Result<(Vec<Token>, Vec<usize>), TokenizeError> {
+    let mut tokens = presized_tokens(srcfile);
+    let mut trace = vec![];
+
+    tokenize_impl(
+        srcfile,
+        fn_matchers,
+        0..usize::MAX,
+        TokenizeLimits::default(),
+        &mut tokens,
+        &mut trace,
+    )?;
+
+    Ok((tokens, trace))
+}
 
-    use fancy_regex::Regex as RegexEh;
+/// `tokenize` writing a step by step trace into `sink`: the position, every
+/// matcher tried (by index) with its answer, the token chosen and the new
+/// position, write errors of `sink` are ignored
+///
+/// The trace hooks are no-op for `tokenize`, it pays nothing for them.
+pub fn tokenize_debug(
+    srcfile: &SrcFileInfo,
+    fn_matchers: &[FnMatcher],
+    sink: &mut impl io::Write,
+) -> TokenizeResult {
+    let mut tokens = vec![];
 
-    use proc_macros::make_token_matcher_rules;
+    tokenize_impl(
+        srcfile,
+        fn_matchers,
+        0..usize::MAX,
+        TokenizeLimits::default(),
+        &mut tokens,
+        &mut DebugTrace { srcfile, sink },
+    )?;
 
-    use crate::{str2sym, Span, TokenizeErrorReason, TokenMatchResult, TokenizeResult};
+    Ok(tokens)
+}
 
+/// Hooks of `tokenize_impl` into its steps, no-op by default
+trait TokenizeTrace {
+    /// Before matching at `bytes_pos`
+    fn at(&mut self, _bytes_pos: usize) {}
+
+    /// Answer of the matcher `i` (the tokens of a match)
+    fn tried(
+        &mut self,
+        _i: usize,
+        _res: Option<Result<&[Token], &TokenizeErrorReason>>,
+    ) {
+    }
 
-    pub fn trim(res: TokenizeResult) -> TokenizeResult {
-        res.and_then(|toks| {Ok(
-            toks
-            .into_iter()
-            .filter(|tok| {
-                if tok.check_names_in(&[
-                    "newline",
-                    "sp",
-                    "sharp_line_comment",
-                    "slash_line_comment"
-                ])
-                {
-                    false
-                }
-                else {
-                    true
-                }})
-            .collect::<Vec<Token>>()
-        )})
+    /// The matcher `i` matched, `tokens` are the ones kept, now at
+    /// `bytes_pos`
+    fn chosen(&mut self, _i: usize, _tokens: &[Token], _bytes_pos: usize) {}
+
+    fn error(&mut self, _reason: &TokenizeErrorReason) {}
+}
+
+impl TokenizeTrace for () {}
+
+/// `tokenize_traced`: the matcher index of each token
+impl TokenizeTrace for Vec<usize> {
+    fn chosen(&mut self, i: usize, tokens: &[Token], _bytes_pos: usize) {
+        self.extend(std::iter::repeat_n(i, tokens.len()));
     }
+}
 
-    ///
-    /// handle this token type:
-    ///
-    /// 1. contains anything but delimiter
-    /// 1. delimiter can be escaped char
-    ///
-    pub fn aux_strlike_m(
-        source: &str,
-        from: usize,
-        prefix: &str,
-        postfix: &str,
-        escape_char: char,
-    ) -> Option<Result<Token, TokenizeErrorReason>> {
-        debug_assert!(!prefix.is_empty());
-        debug_assert!(!postfix.is_empty());
+/// `tokenize_debug`: every hook written as a line of `sink`
+struct DebugTrace<'a, W> {
+    srcfile: &'a SrcFileInfo,
+    sink: &'a mut W,
+}
 
-        if !source.starts_with(prefix) {
-            return None;
+impl<W: io::Write> TokenizeTrace for DebugTrace<'_, W> {
+    fn at(&mut self, bytes_pos: usize) {
+        let SrcLoc { ln, col } = self.srcfile.boffset2srcloc(bytes_pos);
+        let _ = writeln!(self.sink, "@{bytes_pos} ({ln}:{col})");
+    }
+
+    fn tried(
+        &mut self,
+        i: usize,
+        res: Option<Result<&[Token], &TokenizeErrorReason>>,
+    ) {
+        let _ = match res {
+            None => writeln!(self.sink, "  #{i} -"),
+            Some(Ok(toks)) => {
+                let toks = toks
+                    .iter()
+                    .map(|tok| {
+                        format!(
+                            "{} {:?} {:?}",
+                            tok.name_string(),
+                            tok.value_string(),
+                            tok.span
+                        )
+                    })
+                    .collect::<Vec<_>>();
+
+                writeln!(self.sink, "  #{i} {}", toks.join(", "))
+            }
+            Some(Err(reason)) => writeln!(self.sink, "  #{i} {reason:?}"),
+        };
+    }
+
+    fn chosen(&mut self, i: usize, tokens: &[Token], bytes_pos: usize) {
+        let names = if tokens.is_empty() {
+            "(skip)".to_owned()
+        } else {
+            tokens
+                .iter()
+                .map(|tok| tok.name_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let _ = writeln!(self.sink, "=> #{i} {names} -> @{bytes_pos}");
+    }
+
+    fn error(&mut self, reason: &TokenizeErrorReason) {
+        let _ = writeln!(self.sink, "=> error {reason:?}");
+    }
+}
+
+/// The loop of the `tokenize` family: from bytes offset `bounds.start`
+/// (a token boundary) until the token passing `bounds.end`
+fn tokenize_impl<S, T>(
+    srcfile: &SrcFileInfo,
+    fn_matchers: &S,
+    bounds: Range<usize>,
+    limits: TokenizeLimits,
+    tokens: &mut Vec<Token>,
+    trace: &mut T,
+) -> Result<(), TokenizeError>
+where
+    S: MatcherSet + ?Sized,
+    T: TokenizeTrace + ?Sized,
+{
+    let source = srcfile.get_srcstr();
+    let errsrc = ErrorSrc::new(srcfile);
+    let tokens_base = tokens.len();
+
+    if source.len() > limits.max_bytes {
+        return Err(errsrc.error(TokenizeErrorReason::LimitExceeded, 0));
+    }
+
+    if source.is_empty() {
+        return Ok(());
+    }
+
+    let end = min(bounds.end, source.len());
+    let mut cursor = Cursor::at(srcfile, bounds.start);
+
+    while cursor.bytes_pos < end {
+        let step_base = tokens.len();
+
+        trace.at(cursor.bytes_pos);
+
+        let i = cursor.step(source, fn_matchers, tokens, trace).map_err(
+            |reason| {
+                trace.error(&reason);
+
+                errsrc.match_error(
+                    reason,
+                    cursor.chars_pos,
+                    cursor.bytes_pos,
+                    fn_matchers,
+                )
+            },
+        )?;
+
+        if tokens.len() - tokens_base > limits.max_tokens {
+            let over = tokens[tokens_base + limits.max_tokens];
+
+            tokens.truncate(tokens_base + limits.max_tokens);
+
+            return Err(errsrc.error(
+                TokenizeErrorReason::LimitExceeded,
+                over.char_from.unwrap(),
+            ))
         }
 
-        let mut postfix_iter = postfix.chars().into_iter();
-        let delimiter = postfix_iter.next().unwrap();
-        let mut val = String::new();
+        trace.chosen(i, &tokens[step_base..], cursor.bytes_pos);
+    }
 
-        let mut st = 0;
-        // st:
-        //    0 normal mode
-        //    1 escape mode
-        //    2 tail mode  // collect tail
+    Ok(())
+}
 
-        for c in source[prefix.len()..].chars() {
-            match st {
-                0 => {
-                    if c == escape_char {
-                        st = 1;
-                    } else if c == delimiter {
-                        st = 2;
-                    }
-                    val.push(c);
+/// Matchers driven by `tokenize_impl`, one token per match (`FnMatcher`,
+/// `BoxFnMatcher` ...) or several (`MultiMatchers`)
+trait MatcherSet {
+    /// Index of the first matcher matching at `bytes_pos`, its tokens are
+    /// appended to `out` once checked: contiguous from `bytes_pos`,
+    /// advancing, ending at a char boundary
+    fn match_at<T: TokenizeTrace + ?Sized>(
+        &self,
+        source: &str,
+        bytes_pos: usize,
+        out: &mut Vec<Token>,
+        trace: &mut T,
+    ) -> Result<usize, TokenizeErrorReason>;
+
+    /// `TokenizeError::expected` of an `UnrecognizedToken` at `bytes_pos`
+    fn expected_names(&self, source: &str, bytes_pos: usize) -> Vec<Symbol>;
+}
+
+impl<M> MatcherSet for [M]
+where
+    M: Fn(&str, usize) -> Option<TokenMatchResult>,
+{
+    fn match_at<T: TokenizeTrace + ?Sized>(
+        &self,
+        source: &str,
+        bytes_pos: usize,
+        out: &mut Vec<Token>,
+        trace: &mut T,
+    ) -> Result<usize, TokenizeErrorReason> {
+        let (i, tok) = match_token(source, bytes_pos, self, trace)?;
+
+        out.push(tok);
+
+        Ok(i)
+    }
+
+    fn expected_names(&self, source: &str, bytes_pos: usize) -> Vec<Symbol> {
+        expected_names(source, bytes_pos, self)
+    }
+}
+
+/// `MultiTokenMatcher`s as a `MatcherSet`
+struct MultiMatchers<'a>(&'a [MultiTokenMatcher]);
+
+impl MatcherSet for MultiMatchers<'_> {
+    fn match_at<T: TokenizeTrace + ?Sized>(
+        &self,
+        source: &str,
+        bytes_pos: usize,
+        out: &mut Vec<Token>,
+        trace: &mut T,
+    ) -> Result<usize, TokenizeErrorReason> {
+        for (i, fn_matcher) in self.0.iter().enumerate() {
+            let Some(tokres) = fn_matcher(&source[bytes_pos..], bytes_pos)
+            else {
+                trace.tried(i, None);
+                continue;
+            };
+            let toks = match tokres {
+                Ok(toks) => toks,
+                Err(reason) => {
+                    trace.tried(i, Some(Err(&reason)));
+                    return Err(reason);
                 }
-                1 => {
-                    st = 0;
-                    val.push(c);
+            };
+
+            trace.tried(i, Some(Ok(&toks)));
+
+            let mut end = bytes_pos;
+
+            for tok in toks.iter() {
+                if tok.span.from != end {
+                    return Err(TokenizeErrorReason::MisplacedSpan(tok.name));
                 }
-                2 => {
-                    if let Some(mat) = postfix_iter.next() {
-                        if c != mat {
-                            return Some(Err(
-                                TokenizeErrorReason::UnexpectedPostfix,
-                            ));
-                        }
-                    } else {
-                        break;
-                    }
+
+                if tok.span.end < tok.span.from {
+                    return Err(TokenizeErrorReason::ZeroAdvance(tok.name));
                 }
-                _ => unreachable!(),
+
+                if !source.is_char_boundary(tok.span.end) {
+                    return Err(TokenizeErrorReason::BadSpanEnd(tok.name));
+                }
+
+                end = tok.span.end;
+            }
+
+            if end == bytes_pos {
+                return Err(TokenizeErrorReason::ZeroLenToken);
             }
+
+            out.extend(toks);
+
+            return Ok(i);
         }
-        val.pop().unwrap(); // pop delimiter
 
-        let span_len = prefix.len() + val.len() + postfix.len();
-        let span = Span {
-            from,
-            end: from + span_len,
-        };
-        let value = str2sym(&val);
-        let name = str2sym("__aux_tmp");
+        Err(TokenizeErrorReason::UnrecognizedToken)
+    }
+
+    /// probed by the first token of each match
+    fn expected_names(&self, source: &str, bytes_pos: usize) -> Vec<Symbol> {
+        let first_matchers = self
+            .0
+            .iter()
+            .map(|fn_matcher| {
+                move |source: &str, from: usize| {
+                    fn_matcher(source, from).map(|tokres| {
+                        tokres.and_then(|toks| {
+                            toks.first()
+                                .copied()
+                                .ok_or(TokenizeErrorReason::ZeroLenToken)
+                        })
+                    })
+                }
+            })
+            .collect::<Vec<_>>();
 
-        Some(Ok(Token { name, value, span }))
+        expected_names(source, bytes_pos, &first_matchers)
     }
+}
 
-    /// Double quote string
-    #[inline]
-    pub fn dqstr_m(source: &str, from: usize) -> Option<TokenMatchResult> {
-        aux_strlike_m(source, from, "\"", "\"", '\\')
-            .and_then(|res| Some(res.and_then(|tok| Ok(tok.rename("dqstr")))))
+/// Position of a tokenize loop, in bytes and in chars
+struct Cursor {
+    bytes_pos: usize,
+    chars_pos: usize,
+}
+
+impl Cursor {
+    /// At bytes offset `bytes_pos` (a char boundary) of `srcfile`
+    fn at(srcfile: &SrcFileInfo, bytes_pos: usize) -> Self {
+        let SrcLoc { ln, col } = srcfile.boffset2srcloc(bytes_pos);
+
+        Self {
+            bytes_pos,
+            chars_pos: srcfile.lines[ln - 1] + col - 1,
+        }
     }
 
-    /// Double quote string
-    #[inline]
-    pub fn aqstr_m(source: &str, from: usize) -> Option<TokenMatchResult> {
-        aux_strlike_m(source, from, "`", "`", '\\')
-            .and_then(|res| Some(res.and_then(|tok| Ok(tok.rename("aqstr")))))
+    /// The step shared by the `tokenize` loops: match at the position,
+    /// memoize the chars of the tokens, move past them and append the
+    /// ones not skipped to `tokens`. Index of the matcher.
+    fn step<S, T>(
+        &mut self,
+        source: &str,
+        fn_matchers: &S,
+        tokens: &mut Vec<Token>,
+        trace: &mut T,
+    ) -> Result<usize, TokenizeErrorReason>
+    where
+        S: MatcherSet + ?Sized,
+        T: TokenizeTrace + ?Sized,
+    {
+        let base = tokens.len();
+        let i = fn_matchers.match_at(source, self.bytes_pos, tokens, trace)?;
+        let mut kept = base;
+
+        for j in base..tokens.len() {
+            let mut tok = tokens[j];
+            let char_len = tok.span_chars_count(source);
+
+            tok.char_from = Some(self.chars_pos);
+            tok.char_len = Some(char_len);
+
+            self.chars_pos += char_len;
+            self.bytes_pos = tok.span.end;
+
+            if !tok.is_skip() {
+                tokens[kept] = tok;
+                kept += 1;
+            }
+        }
+        tokens.truncate(kept);
+
+        Ok(i)
     }
 
-    /// Single quote string
-    #[inline]
-    pub fn sqstr_m(source: &str, from: usize) -> Option<TokenMatchResult> {
-        aux_strlike_m(source, from, "'", "'", '\\')
-            .and_then(|res| Some(res.and_then(|tok| Ok(tok.rename("sqstr")))))
+    /// Move one char on, past an unmatched position
+    fn skip_char(&mut self, source: &str) {
+        if let Some(c) = source[self.bytes_pos..].chars().next() {
+            self.bytes_pos += c.len_utf8();
+            self.chars_pos += 1;
+        }
     }
+}
 
-    #[inline]
-    pub fn lit_regex_m(source: &str, from: usize) -> Option<TokenMatchResult> {
-        aux_strlike_m(source, from, "/", "/", '\\')
-    .and_then(|res|
-        match res {
-            Ok(mut tok) => {
-                lazy_static::lazy_static! {
-                    pub static ref ALPHABET__: HashSet<char> = ('a'..='z').chain('A'..='Z').collect();
+/// First matcher (index, token) matching at `bytes_pos`,
+/// the token should start at `bytes_pos` and advance
+fn match_token<M, T>(
+    source: &str,
+    bytes_pos: usize,
+    fn_matchers: &[M],
+    trace: &mut T,
+) -> Result<(usize, Token), TokenizeErrorReason>
+where
+    M: Fn(&str, usize) -> Option<TokenMatchResult>,
+    T: TokenizeTrace + ?Sized,
+{
+    for (i, fn_matcher) in fn_matchers.iter().enumerate() {
+        if let Some(tokres) = fn_matcher(&source[bytes_pos..], bytes_pos) {
+            let tok = match tokres {
+                Ok(tok) => tok,
+                Err(reason) => {
+                    trace.tried(i, Some(Err(&reason)));
+                    return Err(reason);
                 }
+            };
 
-                let mut tokv = tok.value_string();
-                if tokv.is_empty() {  // It' maybe slash comment
-                    return None;
-                }
+            trace.tried(i, Some(Ok(std::slice::from_ref(&tok))));
 
-                tokv.insert(0, '/');
-                tokv.push('/');
+            if tok.has_flag(FLAG_LINE_START)
+                && bytes_pos > 0
+                && source.as_bytes()[bytes_pos - 1] != b'\n'
+            {
+                continue;
+            }
 
-                if let Some(nxtc) = source[tok.span_len()..].chars().next() {
-                    if ALPHABET__.contains(&nxtc) {
-                        tokv.push(nxtc);
+            if tok.span_len() == 0 {
+                return Err(TokenizeErrorReason::ZeroLenToken);
+            }
 
-                        let span = Span {
-                            from,
-                            end: from + tok.span_len() + nxtc.to_string().len(),
-                        };
+            if tok.span.end <= bytes_pos {
+                return Err(TokenizeErrorReason::ZeroAdvance(tok.name));
+            }
+
+            if tok.span.from != bytes_pos {
+                return Err(TokenizeErrorReason::MisplacedSpan(tok.name));
+            }
+
+            if !source.is_char_boundary(tok.span.end) {
+                return Err(TokenizeErrorReason::BadSpanEnd(tok.name));
+            }
 
-                        tok.span = span;
+            return Ok((i, tok));
+        } else {
+            trace.tried(i, None);
+        }
+    }
+
+    Err(TokenizeErrorReason::UnrecognizedToken)
+}
+
+/// `tokenize` as a stream in position order, an error is yielded inline
+/// and the tokenizing goes on one char after it
+pub fn tokenize_iter<'a>(
+    srcfile: &'a SrcFileInfo,
+    fn_matchers: &'a [FnMatcher],
+) -> impl Iterator<Item = Result<Token, TokenizeError>> + 'a {
+    let source = srcfile.get_srcstr();
+    let errsrc = ErrorSrc::new(srcfile);
+    let mut cursor = Cursor { bytes_pos: 0, chars_pos: 0 };
+    let mut stepped = Vec::with_capacity(1);
+
+    std::iter::from_fn(move || {
+        while cursor.bytes_pos < source.len() {
+            match cursor.step(source, fn_matchers, &mut stepped, &mut ()) {
+                Ok(_) => {
+                    if let Some(tok) = stepped.pop() {
+                        return Some(Ok(tok));
                     }
                 }
+                Err(reason) => {
+                    let err = errsrc.match_error(
+                        reason,
+                        cursor.chars_pos,
+                        cursor.bytes_pos,
+                        fn_matchers,
+                    );
 
-                tok.value = str2sym(&tokv);
+                    cursor.skip_char(source);
 
-                Some(Ok(tok.rename("lit_regex")))
-            },
-            _ => unreachable!(),
+                    return Some(Err(err));
+                }
+            }
         }
-    )
-    }
 
+        None
+    })
+}
 
-    /// handle this heredoc:
-    pub fn heredoc_m(
-        source: &str,
-        from: usize,
-    ) -> Option<Result<Token, TokenizeErrorReason>> {
-        lazy_static::lazy_static! {
-            pub static ref HEREDOC_2_REG_EH: RegexEh = RegexEh::new(
-                r#"^(<<<|<<-|<<|<-)[[:blank:]]*(.+)([[:blank:]]+.*\n|\n)([\s|\S]*?)\n\2"#
-            ).unwrap();
+/// `tokenize` going on after errors: the tokenizing resumes at the next
+/// position where a token named in `sync` (e.g. `semi`, `newline`, `rbrace`)
+/// matches, the region in between is skipped with one error spanning it
+/// (`TokenizeError::span_end`).
+///
+/// Only the sync matchers scan the skipped region, i.e. the matchers
+/// emitting a sync token for a one char text (punctuations, `\n` ...)
+/// or for a token before the error.
+pub fn tokenize_recover_sync(
+    srcfile: &SrcFileInfo,
+    fn_matchers: &[FnMatcher],
+    sync: &[&str],
+) -> (Vec<Token>, Vec<TokenizeError>) {
+    let source = srcfile.get_srcstr();
+    let errsrc = ErrorSrc::new(srcfile);
+    let mut tokens = presized_tokens(srcfile);
+    let mut errors = vec![];
+
+    let mut is_sync = sync_matchers(fn_matchers, sync);
+    let mut cursor = Cursor { bytes_pos: 0, chars_pos: 0 };
+
+    while cursor.bytes_pos < source.len() {
+        let step_base = tokens.len();
+
+        match cursor.step(source, fn_matchers, &mut tokens, &mut ()) {
+            Ok(i) => {
+                is_sync[i] |= tokens[step_base..]
+                    .iter()
+                    .any(|tok| tok.check_names_in(sync));
+            }
+            Err(reason) => {
+                let err = errsrc.match_error(
+                    reason,
+                    cursor.chars_pos,
+                    cursor.bytes_pos,
+                    fn_matchers,
+                );
+
+                // skip to the next sync token
+                while cursor.bytes_pos < source.len() {
+                    cursor.skip_char(source);
+
+                    let bytes_pos = cursor.bytes_pos;
+                    let at_sync = |(fn_matcher, _): (&FnMatcher, _)| {
+                        matches!(
+                            fn_matcher(&source[bytes_pos..], bytes_pos),
+                            Some(Ok(tok)) if tok.check_names_in(sync)
+                                && tok.span.from == bytes_pos
+                                && tok.span.end > bytes_pos
+                        )
+                    };
+
+                    if bytes_pos < source.len()
+                        && fn_matchers
+                            .iter()
+                            .zip(is_sync.iter())
+                            .filter(|(_, is_sync)| **is_sync)
+                            .any(at_sync)
+                    {
+                        break;
+                    }
+                }
+
+                errors.push(TokenizeError {
+                    skipped_end: Some(cursor.chars_pos),
+                    ..err
+                });
+            }
         }
+    }
 
-        let cap_opt = HEREDOC_2_REG_EH.captures(source).unwrap();
+    (tokens, errors)
+}
 
-        if let Some(cap) = cap_opt {
-            let bytes_len = cap.get(0).unwrap().as_str().len();
-            let span = Span {
-                from,
-                end: from + bytes_len,
-            };
+/// Which of `fn_matchers` emit a token named in `sync` for a one char text,
+/// probed with the chars of `expected_names`
+fn sync_matchers(fn_matchers: &[FnMatcher], sync: &[&str]) -> Vec<bool> {
+    let mut buf = [0u8; 4];
+
+    fn_matchers
+        .iter()
+        .map(|fn_matcher| {
+            EXPECTED_PROBES.chars().any(|probe| {
+                matches!(
+                    fn_matcher(probe.encode_utf8(&mut buf), 0),
+                    Some(Ok(tok)) if tok.check_names_in(sync)
+                )
+            })
+        })
+        .collect()
+}
+
+/// `tokenize` only the part overlapping bytes `range` (e.g. the visible part
+/// in an editor), the spans remain absolute (offsets of the whole source).
+///
+/// It starts at the line of `range.start` (a safe token boundary unless
+/// inside a multi-line token) and stops after the token passing `range.end`.
+pub fn tokenize_range(
+    srcfile: &SrcFileInfo,
+    fn_matchers: &[FnMatcher],
+    range: Range<usize>,
+) -> TokenizeResult {
+    let source = srcfile.get_srcstr();
+    let mut tokens = vec![];
+
+    let ln = srcfile.boffset2srcloc(min(range.start, source.len())).ln;
+
+    tokenize_impl(
+        srcfile,
+        fn_matchers,
+        srcfile.blines[ln - 1]..range.end,
+        TokenizeLimits::default(),
+        &mut tokens,
+        &mut (),
+    )?;
+    tokens.retain(|tok| tok.span.end > range.start);
+
+    Ok(tokens)
+}
+
+/// `tokenize` from bytes offset `start` (a token boundary, e.g. the start of
+/// the first token touched by an edit) to the end, the spans remain absolute
+pub fn tokenize_from(
+    srcfile: &SrcFileInfo,
+    fn_matchers: &[FnMatcher],
+    start: usize,
+) -> TokenizeResult {
+    let source = srcfile.get_srcstr();
+    let errsrc = ErrorSrc::new(srcfile);
+    let mut tokens = vec![];
+
+    if !source.is_char_boundary(start) {
+        let floor = (0..=min(start, source.len()))
+            .rev()
+            .find(|i| source.is_char_boundary(*i))
+            .unwrap_or(0);
+
+        return Err(errsrc.error(
+            TokenizeErrorReason::BadStart,
+            source[..floor].chars().count(),
+        ));
+    }
+
+    tokenize_impl(
+        srcfile,
+        fn_matchers,
+        start..usize::MAX,
+        TokenizeLimits::default(),
+        &mut tokens,
+        &mut (),
+    )?;
+
+    Ok(tokens)
+}
+
+/// `tokenize` with boxed matchers, e.g. closures capturing a comment prefix
+/// or a quote char read from the configuration, or the matchers of
+/// `prelude::matchers_from_rules`
+pub fn tokenize_boxed(
+    srcfile: &SrcFileInfo,
+    matchers: &[BoxFnMatcher],
+) -> TokenizeResult {
+    let mut tokens = presized_tokens(srcfile);
+
+    tokenize_impl(
+        srcfile,
+        matchers,
+        0..usize::MAX,
+        TokenizeLimits::default(),
+        &mut tokens,
+        &mut (),
+    )?;
+
+    Ok(tokens)
+}
+
+/// `tokenize` with matchers which may emit several tokens per match,
+/// the emitted tokens should be in order and have contiguous spans.
+pub fn tokenize_multi(
+    srcfile: &SrcFileInfo,
+    fn_matchers: &[MultiTokenMatcher],
+) -> TokenizeResult {
+    let mut tokens = presized_tokens(srcfile);
+
+    tokenize_impl(
+        srcfile,
+        &MultiMatchers(fn_matchers),
+        0..usize::MAX,
+        TokenizeLimits::default(),
+        &mut tokens,
+        &mut (),
+    )?;
+
+    Ok(tokens)
+}
+
+/// Declare a tokenizer module: the `make_token_matcher_rules!` rules and
+/// a `tokenize` dropping the `trivia` tokens.
+///
+/// ```
+/// m6lexerkit::define_tokenizer! {
+///     calc,
+///     trivia: [sp],
+///     rules {
+///         id  => "[[:alpha:]_][[:alnum:]_]*",
+///         add => r"\+",
+///         sp  => "[[:blank:]]+"
+///     }
+/// }
+///
+/// let srcfile = m6lexerkit::SrcFileInfo::from_str("a + b".to_owned());
+/// let tokens = calc::tokenize(&srcfile).unwrap();
+///
+/// assert_eq!(tokens.len(), 3);
+/// assert_eq!(calc::TOKEN_NAMES, ["id", "add", "sp"]);
+/// ```
+#[macro_export]
+macro_rules! define_tokenizer {
+    (
+        $name:ident,
+        trivia: [ $($trivia:ident),* $(,)? ],
+        rules { $($rules:tt)* }
+    ) => {
+        pub mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+            use $crate as m6lexerkit;
+            use $crate::TokenMatchResult;
+
+            $crate::make_token_matcher_rules! { $($rules)* }
+
+            /// Token names dropped by `tokenize`
+            pub const TRIVIA_NAMES: &[&str] = &[$(stringify!($trivia)),*];
+
+            pub fn tokenize(
+                srcfile: &$crate::SrcFileInfo,
+            ) -> $crate::TokenizeResult {
+                $crate::tokenize(srcfile, &MATCHERS[..]).map(|tokens| {
+                    tokens
+                        .into_iter()
+                        .filter(|tok| !tok.check_names_in(TRIVIA_NAMES))
+                        .collect()
+                })
+            }
+        }
+    };
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+//// Auxiliary
+
+pub fn sym2str(sym: Symbol) -> String {
+    INTERNER
+        .with(|interner| interner.borrow().resolve(sym.0).unwrap().to_owned())
+}
+
+pub fn str2sym(s: &str) -> Symbol {
+    Symbol(INTERNER.with(|interner| interner.borrow_mut().get_or_intern(s)))
+}
+
+/// Intern all `strs` with one interner borrow, symbols are in order
+pub fn str2syms(strs: &[&str]) -> Vec<Symbol> {
+    INTERNER.with(|interner| {
+        let mut interner = interner.borrow_mut();
+
+        strs.iter()
+            .map(|s| Symbol(interner.get_or_intern(s)))
+            .collect()
+    })
+}
+
+/// Number of strings interned on this thread
+pub fn interner_len() -> usize {
+    INTERNER.with(|interner| interner.borrow().len())
+}
+
+/// Run `f` with a fresh interner (for this thread) and restore the previous
+/// one afterwards, so strings interned inside `f` are freed with it.
+///
+/// `Symbol`s must not cross the scope boundary in either direction,
+/// move the tokens out with `Token::reintern`.
+pub fn with_fresh_interner<R>(f: impl FnOnce() -> R) -> R {
+    /// the symbols cached for the outer interner
+    struct Restore(Option<TriviaSyms>, Vec<Option<Symbol>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let interner =
+                OUTER_INTERNERS.with(|outer| outer.borrow_mut().pop().unwrap());
+
+            INTERNER.with(|cur| *cur.borrow_mut() = interner);
+            TRIVIA_SYMS.with(|cur| *cur.borrow_mut() = self.0.take());
+            MATCHER_NAME_SYMS
+                .with(|cur| *cur.borrow_mut() = std::mem::take(&mut self.1));
+        }
+    }
+
+    OUTER_INTERNERS.with(|outer| {
+        outer.borrow_mut().push(
+            INTERNER.with(|cur| cur.replace(StringInterner::default())),
+        )
+    });
+    let _restore = Restore(
+        TRIVIA_SYMS.with(|cur| cur.take()),
+        MATCHER_NAME_SYMS.with(|cur| cur.take()),
+    );
+
+    f()
+}
+
+thread_local! {
+    /// interners suspended by the enclosing `with_fresh_interner`s
+    static OUTER_INTERNERS: RefCell<Vec<StringInterner>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+impl Token {
+    /// Move the token out of `with_fresh_interner`: `name` and `value`
+    /// resolved in the current interner are interned into the enclosing one,
+    /// so the token stays valid after the scope (unchanged outside a scope)
+    pub fn reintern(self) -> Token {
+        let (name, value) = (self.name_string(), self.value_string());
+
+        OUTER_INTERNERS.with(|outer| match outer.borrow_mut().last_mut() {
+            Some(interner) => Token {
+                name: Symbol(interner.get_or_intern(name)),
+                value: Symbol(interner.get_or_intern(value)),
+                ..self
+            },
+            None => self,
+        })
+    }
+}
+
+/// Case-insensitive compare without allocation
+fn eq_ci(lhs: &str, rhs: &str) -> bool {
+    lhs.chars()
+        .flat_map(char::to_lowercase)
+        .eq(rhs.chars().flat_map(char::to_lowercase))
+}
+
+
+
+pub mod prelude {
+    use std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        ops::Range,
+        sync::Arc,
+    };
+
+    use fancy_regex::Regex as RegexEh;
+
+    use proc_macros::make_token_matcher_rules;
+
+    use crate::{
+        str2sym, str2syms, tokenize, BoxFnMatcher, ErrorSrc, FnMatcher,
+        TokenMatcher, Regex, Span, SrcFileInfo, Symbol, TokenClassifier,
+        TokenKind, TokenizeError, TokenizeErrorReason, TokenMatchResult,
+        TokenizeResult, FLAG_LINE_START,
+    };
+
+
+    /// `tokenize` a string directly (through `SrcFileInfo::from_str`)
+    ///
+    /// ```
+    /// use m6lexerkit::{make_token_matcher_rules, prelude::tokenize_str, TokenMatchResult};
+    ///
+    /// make_token_matcher_rules! {
+    ///     id  => "[[:alpha:]_][[:alnum:]_]*",
+    ///     sp  => "[[:blank:]]+",
+    ///     add => r"\+"
+    /// }
+    ///
+    /// let tokens = tokenize_str("a + b", &MATCHERS[..]).unwrap();
+    ///
+    /// assert_eq!(
+    ///     tokens.iter().map(|tok| tok.name_string()).collect::<Vec<_>>(),
+    ///     ["id", "sp", "add", "sp", "id"]
+    /// );
+    /// ```
+    pub fn tokenize_str(src: &str, fn_matchers: &[FnMatcher]) -> TokenizeResult {
+        tokenize(&SrcFileInfo::from_str(src.to_owned()), fn_matchers)
+    }
+
+    /// Tokenize the text of `tok` inside its `prefix_len`/`postfix_len`
+    /// bytes delimiters (e.g. the body of a template string between its
+    /// quotes) with `inner_matchers`, the spans are offsets in `source` like
+    /// those of `tok`
+    ///
+    /// A `tok` not spanning `source` (see `Token::inner_span`) is a
+    /// `BadSpanEnd` error, the positions of the errors are those in `source`.
+    pub fn sub_tokenize(
+        tok: &Token,
+        source: &str,
+        prefix_len: usize,
+        postfix_len: usize,
+        inner_matchers: &[FnMatcher],
+    ) -> TokenizeResult {
+        let whole = || SrcFileInfo::from_str(source.to_owned());
+        let chars_before = |bytes_pos: usize| {
+            source.get(..bytes_pos).map_or(0, |head| head.chars().count())
+        };
+
+        let Some(inner) = tok.inner_span(source, prefix_len, postfix_len)
+        else {
+            return Err(ErrorSrc::new(&whole()).error(
+                TokenizeErrorReason::BadSpanEnd(tok.name),
+                chars_before(tok.span.from),
+            ));
+        };
+        let frag = SrcFileInfo::from_str(
+            source[inner.from..inner.end].to_owned(),
+        )
+        .with_base_offset(inner.from);
+
+        match tokenize(&frag, inner_matchers) {
+            Ok(tokens) => Ok(tokens
+                .into_iter()
+                .map(|tok| tok.shift_span(frag.base_offset() as isize))
+                .collect()),
+            Err(err) => {
+                let base = chars_before(inner.from);
+
+                Err(TokenizeError {
+                    start: base + err.start,
+                    src: Arc::new(whole()),
+                    skipped_end: err.skipped_end.map(|end| base + end),
+                    ..err
+                })
+            }
+        }
+    }
+
+    /// Matchers of `(name, pattern)` rules loaded at runtime (a config file
+    /// ...), tried in order by `tokenize_boxed` like the rules of
+    /// `make_token_matcher_rules!`
+    pub fn matchers_from_rules(
+        rules: &[(String, String)],
+    ) -> Result<Vec<BoxFnMatcher>, regex::Error> {
+        rules
+            .iter()
+            .map(|(name, patstr)| {
+                let matcher =
+                    TokenMatcher::try_new(&format!("^({patstr})"), name)?;
+
+                Ok(Box::new(move |source: &str, from: usize| {
+                    matcher.fetch_tok(source, from)
+                }) as BoxFnMatcher)
+            })
+            .collect()
+    }
+
+    /// Intern `names` in order, for symbols known up front (keywords, etc.)
+    ///
+    /// Interning is thread-local: seed each thread (or
+    /// `with_fresh_interner` scope) which compares against the result.
+    /// Seeded before anything else, the ids are `0..names.len()`
+    /// (duplicated names share the first id).
+    pub fn seed_interner(names: &[&str]) -> Vec<Symbol> {
+        str2syms(names)
+    }
+
+    /// Rename each token whose value is a key of `table` to the mapped name
+    pub fn rename_all(tokens: &[Token], table: &HashMap<&str, &str>) -> Vec<Token> {
+        rename_all_by(tokens, table, |tok| tok.value)
+    }
+
+    /// Rename each token whose name is a key of `table` to the mapped name
+    pub fn rename_all_by_name(
+        tokens: &[Token],
+        table: &HashMap<&str, &str>,
+    ) -> Vec<Token> {
+        rename_all_by(tokens, table, |tok| tok.name)
+    }
+
+    fn rename_all_by(
+        tokens: &[Token],
+        table: &HashMap<&str, &str>,
+        key: impl Fn(&Token) -> Symbol,
+    ) -> Vec<Token> {
+        let table = table
+            .iter()
+            .map(|(k, v)| (str2sym(k), *v))
+            .collect::<HashMap<Symbol, &str>>();
+
+        tokens
+            .iter()
+            .map(|tok| match table.get(&key(tok)) {
+                Some(name) => tok.rename(name),
+                None => *tok,
+            })
+            .collect()
+    }
+
+    /// Anchor `matcher` at the line start (column 1), e.g. a markdown header
+    /// `header => @at_line_start(header_m)`.
+    ///
+    /// The token is flagged `FLAG_LINE_START` and `tokenize` checks the byte
+    /// before, so an error from `matcher` still stops the tokenizing.
+    pub fn at_line_start(
+        matcher: FnMatcher,
+    ) -> impl Fn(&str, usize) -> Option<TokenMatchResult> {
+        move |source, from| {
+            matcher(source, from)
+                .map(|res| res.map(|tok| tok.with_flag(FLAG_LINE_START)))
+        }
+    }
+
+    /// One rule of several alternative matchers tried in order, the first
+    /// success is renamed to `name` (else the first error),
+    /// e.g. `string => @alt("string", &[dqstr_m, sqstr_m, aqstr_m])`
+    pub fn alt<'a>(
+        name: &'a str,
+        matchers: &'a [FnMatcher],
+    ) -> impl Fn(&str, usize) -> Option<TokenMatchResult> + 'a {
+        move |source, from| {
+            let mut first_err = None;
+
+            for matcher in matchers {
+                match matcher(source, from) {
+                    Some(Ok(tok)) => return Some(Ok(tok.rename(name))),
+                    Some(Err(reason)) => {
+                        first_err.get_or_insert(reason);
+                    }
+                    None => (),
+                }
+            }
+
+            first_err.map(Err)
+        }
+    }
+
+    /// Operators tried longest first, so the declaration order doesn't matter
+    /// (`=` never shadows `==`), see `operator_table`
+    #[derive(Clone, Debug)]
+    pub struct OperatorTable {
+        /// (operator, token name), longest operator first
+        ops: Vec<(String, String)>,
+    }
+
+    impl OperatorTable {
+        pub fn new(ops: &[&str]) -> Self {
+            let mut seen = HashSet::new();
+            let mut ops = ops
+                .iter()
+                .filter(|op| !op.is_empty() && seen.insert(**op))
+                .map(|op| (op.to_string(), operator_name(op)))
+                .collect::<Vec<_>>();
+
+            ops.sort_by_key(|(op, _)| std::cmp::Reverse(op.len()));
+
+            Self { ops }
+        }
+
+        pub fn fetch_tok(
+            &self,
+            text: &str,
+            start: usize,
+        ) -> Option<TokenMatchResult> {
+            let (op, name) = self
+                .ops
+                .iter()
+                .find(|(op, _)| text.starts_with(op.as_str()))?;
+
+            let span = Span {
+                from: start,
+                end: start + op.len(),
+            };
+
+            Some(Ok(Token::new(name, op, span)))
+        }
+
+        /// `classifier` taking the token names of the table as `Operator`,
+        /// for the generated names out of `DEFAULT_OPERATOR_NAMES`
+        pub fn classify_with(
+            &self,
+            classifier: TokenClassifier,
+        ) -> TokenClassifier {
+            self.ops.iter().fold(classifier, |classifier, (_, name)| {
+                classifier.with(name, TokenKind::Operator)
+            })
+        }
+
+        /// A matcher per operator, longest operator first
+        pub fn into_matchers(self) -> Vec<BoxFnMatcher> {
+            self.ops
+                .into_iter()
+                .map(|(op, name)| {
+                    Box::new(move |source: &str, from: usize| {
+                        let span = Span {
+                            from,
+                            end: from + op.len(),
+                        };
+
+                        source
+                            .starts_with(op.as_str())
+                            .then(|| Ok(Token::new(&name, &op, span)))
+                    }) as BoxFnMatcher
+                })
+                .collect()
+        }
+    }
+
+    /// Matchers of `ops` for `tokenize_boxed`, sorted longest operator
+    /// first. The tokens are named like the examples (`=` -> `assign`,
+    /// `==` -> `eq`, `===` -> `realeq`, see `OPERATOR_NAMES`), the other
+    /// operators after their chars (`+=` -> `add_assign`):
+    ///
+    /// ```
+    /// use m6lexerkit::{prelude::operator_table, tokenize_boxed, SrcFileInfo};
+    ///
+    /// let srcfile = SrcFileInfo::from_str("===".to_owned());
+    /// let matchers = operator_table(&["=", "=="]);
+    /// let tokens = tokenize_boxed(&srcfile, &matchers).unwrap();
+    ///
+    /// assert!(tokens[0].check_name("eq") && tokens[1].check_name("assign"));
+    /// ```
+    ///
+    /// A plain `FnMatcher` keeps an `OperatorTable` in a `lazy_static` and
+    /// calls its `fetch_tok`, like the generated matchers do with their
+    /// `TokenMatcher`.
+    pub fn operator_table(ops: &[&str]) -> Vec<BoxFnMatcher> {
+        OperatorTable::new(ops).into_matchers()
+    }
+
+    /// (operator, token name) of the examples
+    pub const OPERATOR_NAMES: &[(&str, &str)] = &[
+        ("=", "assign"),
+        ("==", "eq"),
+        ("===", "realeq"),
+        ("!=", "neq"),
+        ("!==", "nrealeq"),
+        ("++", "inc"),
+        ("--", "dec"),
+        ("!", "not"),
+        ("+", "add"),
+        ("-", "sub"),
+        ("*", "mul"),
+        ("/", "div"),
+        ("%", "percent"),
+        (".", "dot"),
+        (">=", "ge"),
+        ("<=", "le"),
+        ("<", "lt"),
+        (">", "gt"),
+        ("&&", "and"),
+        ("||", "or"),
+        ("=>", "double_arrow"),
+        ("->", "single_arrow"),
+    ];
+
+    /// Token name of an operator, the name in `OPERATOR_NAMES` or a name
+    /// per char joined by `_`
+    fn operator_name(op: &str) -> String {
+        if let Some((_, name)) = OPERATOR_NAMES.iter().find(|(x, _)| *x == op)
+        {
+            return name.to_string();
+        }
+
+        op.chars()
+            .map(|c| {
+                let mut buf = [0u8; 4];
+                let s = c.encode_utf8(&mut buf);
+
+                if let Some((_, name)) =
+                    OPERATOR_NAMES.iter().find(|(x, _)| *x == s)
+                {
+                    return name.to_string();
+                }
+
+                match c {
+                    '&' => "amp".to_owned(),
+                    '|' => "pipe".to_owned(),
+                    '^' => "caret".to_owned(),
+                    '~' => "tilde".to_owned(),
+                    ':' => "colon".to_owned(),
+                    '?' => "question".to_owned(),
+                    '@' => "at".to_owned(),
+                    '#' => "sharp".to_owned(),
+                    '$' => "dollar".to_owned(),
+                    c if c.is_alphanumeric() || c == '_' => c.to_string(),
+                    c => format!("u{:x}", c as u32),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("_")
+    }
+
+    /// Replace `tokens[range]` with `replacement` (e.g. desugaring `x++`),
+    /// the replacement spans are kept as given.
+    ///
+    /// With `shift`, the tokens after `range` are moved by the change of the
+    /// covered bytes: the extent (first `from` to last `end`) of `replacement`
+    /// minus that of the removed tokens, so they stay contiguous with a
+    /// longer or shorter replacement.
+    ///
+    /// False, with `tokens` untouched, if a shifted span would go below zero.
+    pub fn splice_tokens(
+        tokens: &mut Vec<Token>,
+        range: Range<usize>,
+        replacement: Vec<Token>,
+        shift: bool,
+    ) -> bool {
+        let extent = |toks: &[Token]| match (toks.first(), toks.last()) {
+            (Some(first), Some(last)) => {
+                (last.span.end - first.span.from) as isize
+            }
+            _ => 0,
+        };
+
+        let delta = extent(&replacement) - extent(&tokens[range.clone()]);
+        let shift = shift && delta != 0;
+
+        if shift
+            && tokens[range.end..]
+                .iter()
+                .any(|tok| tok.span.checked_shift(delta).is_none())
+        {
+            return false;
+        }
+
+        let end = range.start + replacement.len();
+
+        tokens.splice(range, replacement);
+
+        if shift {
+            for tok in tokens[end..].iter_mut() {
+                *tok = tok.shift_span(delta);
+            }
+        }
+
+        true
+    }
+
+    /// Merge the runs of adjacent tokens (one ends where the next starts)
+    /// with the same name in `names` into one token, spanning the run and
+    /// concatenating the values, e.g. consecutive `sp` after a rewrite
+    pub fn coalesce(tokens: Vec<Token>, names: &[&str]) -> Vec<Token> {
+        let names = names.iter().map(|name| str2sym(name)).collect::<Vec<_>>();
+        let mut res: Vec<Token> = Vec::with_capacity(tokens.len());
+
+        for tok in tokens {
+            if let Some(last) = res.last_mut() {
+                if last.name == tok.name
+                    && names.contains(&tok.name)
+                    && last.span.end == tok.span.from
+                {
+                    last.value = str2sym(&format!(
+                        "{}{}",
+                        last.value_string(),
+                        tok.value_string()
+                    ));
+                    last.span.end = tok.span.end;
+                    last.flags |= tok.flags;
+                    last.char_len =
+                        last.char_len.zip(tok.char_len).map(|(a, b)| a + b);
+                    continue;
+                }
+            }
+
+            res.push(tok);
+        }
+
+        res
+    }
+
+    /// Exact source text between the tokens `a` and `b` (`a` before `b`),
+    /// e.g. the whitespace run of an indentation or table DSL
+    ///
+    /// None if `b` is before `a` or the tokens aren't spans of `source`
+    pub fn separator_between<'a>(
+        source: &'a str,
+        a: &Token,
+        b: &Token,
+    ) -> Option<&'a str> {
+        source.get(a.span.end..b.span.from)
+    }
+
+    /// Two rules both matching at the start of `sample`, `first` is tried
+    /// first so `second` never gets the input
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub struct OverlapWarning {
+        pub first: String,
+        pub second: String,
+        pub sample: String,
+    }
+
+    /// Samples tried besides the literal patterns
+    const OVERLAP_SAMPLES: &[&str] = &[
+        "a", "abc", "_x1", "0", "42", "1.5", "0xff", " ", "\t", "\n",
+        "\"s\"", "'s'", "`s`", "//c", "/*c*/", "#c",
+    ];
+
+    /// Developer aid for the rules (`(name, pattern)` in matching order),
+    /// report the pairs where the first matches a sample at least as long
+    /// as the second does and so takes it over, e.g. `assign => "="` before
+    /// `eq => "=="` cuts `==`.
+    ///
+    /// The samples are the literal patterns and some common lexemes,
+    /// the error of the first malformed pattern is returned.
+    pub fn check_matcher_overlap(
+        names_and_pats: &[(&str, &str)],
+    ) -> Result<Vec<OverlapWarning>, regex::Error> {
+        let regs = names_and_pats
+            .iter()
+            .map(|(_, pat)| Regex::new(&format!("^(?:{pat})")))
+            .collect::<Result<Vec<_>, _>>()?;
+        let samples = names_and_pats
+            .iter()
+            .filter_map(|(_, pat)| literal_of(pat))
+            .chain(OVERLAP_SAMPLES.iter().map(|s| s.to_string()))
+            .collect::<Vec<_>>();
+        let mut warnings = vec![];
+
+        for (i, (first, reg1)) in names_and_pats.iter().zip(&regs).enumerate() {
+            for ((second, _), reg2) in
+                names_and_pats.iter().zip(&regs).skip(i + 1)
+            {
+                // `second` is cut or shadowed by `first`
+                let sample = samples.iter().find(|sample| {
+                    let matched_len =
+                        |reg: &Regex| reg.find(sample).map_or(0, |m| m.end());
+                    let len1 = matched_len(reg1);
+
+                    len1 > 0 && matched_len(reg2) >= len1
+                });
+
+                if let Some(sample) = sample {
+                    warnings.push(OverlapWarning {
+                        first: first.0.to_owned(),
+                        second: second.to_string(),
+                        sample: sample.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(warnings)
+    }
+
+    /// The string matched by `pat` if it's a literal (maybe escaped)
+    fn literal_of(pat: &str) -> Option<String> {
+        let mut lit = String::new();
+        let mut chars = pat.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    let escaped = chars.next()?;
+
+                    if escaped.is_alphanumeric() {
+                        return None; // `\d`, `\n` ...
+                    }
+                    lit.push(escaped);
+                }
+                '.' | '^' | '$' | '*' | '+' | '?' | '(' | ')' | '[' | ']'
+                | '{' | '}' | '|' => return None,
+                _ => lit.push(c),
+            }
+        }
+
+        Some(lit).filter(|lit| !lit.is_empty())
+    }
+
+    /// Developer aid for a rule pattern, report the likely mistakes:
+    ///
+    /// - `|` inside a class `[...]` is a literal pipe, not an alternation,
+    ///   `[+|-]` matches `+`, `|` or `-` (use `[+-]` or `(?:\+|-)`)
+    pub fn check_pattern(patstr: &str) -> Vec<String> {
+        let mut warnings = vec![];
+        let mut chars = patstr.char_indices().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '[' => {
+                    // `]` right after `[` or `[^` is a member
+                    chars.next_if(|(_, c)| *c == '^');
+                    chars.next_if(|(_, c)| *c == ']');
+
+                    let mut depth = 1;
+                    let mut pipe = false;
+                    let mut end = patstr.len();
+
+                    while let Some((j, c)) = chars.next() {
+                        match c {
+                            '\\' => {
+                                chars.next();
+                            }
+                            // `[:alpha:]` or a nested class
+                            '[' => depth += 1,
+                            ']' => {
+                                depth -= 1;
+
+                                if depth == 0 {
+                                    end = j + 1;
+                                    break;
+                                }
+                            }
+                            '|' => pipe = true,
+                            _ => (),
+                        }
+                    }
+
+                    let class = &patstr[i..end];
+
+                    if pipe && class != "[|]" {
+                        warnings.push(format!(
+                            "`|` in `{class}` is a literal pipe, \
+                             not an alternation"
+                        ));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        warnings
+    }
+
+    /// Count of each token name, e.g. to spot a rule never (or too often)
+    /// firing
+    pub fn token_histogram(tokens: &[Token]) -> BTreeMap<String, usize> {
+        let mut hist = BTreeMap::new();
+
+        for tok in tokens {
+            *hist.entry(tok.name_string()).or_insert(0) += 1;
+        }
+
+        hist
+    }
+
+    /// Post-process the value matched by `matcher` through `f`
+    /// (e.g. lowercase keywords), the span still points at the source
+    pub fn map_value(
+        matcher: FnMatcher,
+        f: fn(&str) -> String,
+    ) -> impl Fn(&str, usize) -> Option<TokenMatchResult> {
+        move |source, from| {
+            matcher(source, from).map(|res| {
+                res.map(|tok| {
+                    let value = f(&tok.value_string());
+                    tok.mapval(&value)
+                })
+            })
+        }
+    }
+
+    /// Drop the trivia tokens (`Token::is_trivia`)
+    pub fn trim(res: TokenizeResult) -> TokenizeResult {
+        res.map(|toks| {
+            toks
+            .into_iter()
+            .filter(|tok| !tok.is_trivia())
+            .collect::<Vec<Token>>()
+        })
+    }
+
+    /// Drop the tokens named in `names` (e.g. `&["sp", "newline"]`)
+    pub fn trim_names(res: TokenizeResult, names: &[&str]) -> TokenizeResult {
+        res.map(|toks| {
+            toks
+            .into_iter()
+            .filter(|tok| !tok.check_names_in(names))
+            .collect::<Vec<Token>>()
+        })
+    }
+
+    /// Find the token whose span covers the bytes `offset`.
+    ///
+    /// `tokens` should be ordered by span (as tokenizer output),
+    /// zero-length token (EOF) covers only its own `from`,
+    /// offset falls in a gap (e.g. trimmed trivia) gets `None`.
+    pub fn token_at(tokens: &[Token], offset: usize) -> Option<&Token> {
+        let idx = tokens.partition_point(|tok| {
+            tok.span.end <= offset
+                && !(tok.span.is_empty() && tok.span.from == offset)
+        });
+
+        let tok = tokens.get(idx)?;
+
+        if tok.span.from <= offset
+            && (offset < tok.span.end || tok.span.is_empty())
+        {
+            Some(tok)
+        } else {
+            None
+        }
+    }
+
+    ///
+    /// handle this token type:
+    ///
+    /// 1. contains anything but delimiter
+    /// 1. delimiter can be escaped char
+    ///
+    pub fn aux_strlike_m(
+        source: &str,
+        from: usize,
+        prefix: &str,
+        postfix: &str,
+        escape_char: char,
+    ) -> Option<Result<Token, TokenizeErrorReason>> {
+        debug_assert!(!prefix.is_empty());
+        debug_assert!(!postfix.is_empty());
+
+        if !source.starts_with(prefix) {
+            return None;
+        }
+
+        let mut postfix_iter = postfix.chars();
+        let delimiter = postfix_iter.next().unwrap();
+        let mut val = String::new();
+
+        let mut st = 0;
+        // st:
+        //    0 normal mode
+        //    1 escape mode
+        //    2 tail mode  // collect tail
+
+        for c in source[prefix.len()..].chars() {
+            match st {
+                0 => {
+                    if c == escape_char {
+                        st = 1;
+                    } else if c == delimiter {
+                        st = 2;
+                    }
+                    val.push(c);
+                }
+                1 => {
+                    st = 0;
+                    val.push(c);
+                }
+                2 => {
+                    if let Some(mat) = postfix_iter.next() {
+                        if c != mat {
+                            return Some(Err(
+                                TokenizeErrorReason::UnexpectedPostfix,
+                            ));
+                        }
+                    } else {
+                        break;
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        if st != 2 {
+            return Some(Err(TokenizeErrorReason::Unterminated));
+        }
+        val.pop(); // pop delimiter (pushed entering the tail mode)
+
+        let span_len = prefix.len() + val.len() + postfix.len();
+        let span = Span {
+            from,
+            end: from + span_len,
+        };
+        Some(Ok(Token::new("__aux_tmp", &val, span)))
+    }
+
+    /// `open ... close` region, nested pairs included (`(a (b) c)`), as one
+    /// token valued by the text inside the outermost pair (`__aux_tmp` like
+    /// `aux_strlike_m`, rename it)
+    ///
+    /// The char after `escape` never opens or closes, unbalanced region is an
+    /// `Unterminated` error.
+    pub fn balanced_m(
+        source: &str,
+        from: usize,
+        open: char,
+        close: char,
+        escape: Option<char>,
+    ) -> Option<TokenMatchResult> {
+        debug_assert_ne!(open, close);
+
+        let body = source.strip_prefix(open)?;
+        let mut depth = 1usize;
+        let mut escaped = false;
+        let mut inner_len = None;
+
+        for (i, c) in body.char_indices() {
+            if escaped {
+                escaped = false;
+            } else if Some(c) == escape {
+                escaped = true;
+            } else if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+
+                if depth == 0 {
+                    inner_len = Some(i);
+                    break;
+                }
+            }
+        }
+
+        let Some(inner_len) = inner_len else {
+            return Some(Err(TokenizeErrorReason::Unterminated));
+        };
+
+        let span = Span {
+            from,
+            end: from + open.len_utf8() + inner_len + close.len_utf8(),
+        };
+
+        Some(Ok(Token::new("__aux_tmp", &body[..inner_len], span)))
+    }
+
+    /// Double quote string
+    #[inline]
+    pub fn dqstr_m(source: &str, from: usize) -> Option<TokenMatchResult> {
+        aux_strlike_m(source, from, "\"", "\"", '\\')
+            .map(|res| res.map(|tok| tok.rename("dqstr")))
+    }
+
+    /// Double quote string
+    #[inline]
+    pub fn aqstr_m(source: &str, from: usize) -> Option<TokenMatchResult> {
+        aux_strlike_m(source, from, "`", "`", '\\')
+            .map(|res| res.map(|tok| tok.rename("aqstr")))
+    }
+
+    /// Single quote string
+    #[inline]
+    pub fn sqstr_m(source: &str, from: usize) -> Option<TokenMatchResult> {
+        aux_strlike_m(source, from, "'", "'", '\\')
+            .map(|res| res.map(|tok| tok.rename("sqstr")))
+    }
+
+    /// Char literal: exactly one (maybe escaped) char between single quotes,
+    /// `'a'`, `'\n'`, `'\x7f'`, `'\u{1F600}'`.
+    ///
+    /// The value is the text between the quotes, escapes are kept as written.
+    pub fn char_lit_m(source: &str, from: usize) -> Option<TokenMatchResult> {
+        let body = source.strip_prefix('\'')?;
+        let mut chars = body.chars();
+
+        let c = match chars.next() {
+            Some(c) if c != '\'' && c != '\n' => c,
+            _ => return Some(Err(TokenizeErrorReason::UnrecognizedToken)),
+        };
+
+        if c == '\\' {
+            let esc = match chars.next() {
+                Some(esc) => esc,
+                None => return Some(Err(TokenizeErrorReason::UnexpectedPostfix)),
+            };
+            let rest = chars.as_str();
+
+            let valid = match esc {
+                'n' | 't' | '\\' | '\'' => true,
+                'x' => {
+                    rest.len() >= 2
+                        && rest.as_bytes()[..2].iter().all(u8::is_ascii_hexdigit)
+                }
+                'u' => rest.strip_prefix('{').and_then(|rest| {
+                    let (hex, _) = rest.split_once('}')?;
+                    u32::from_str_radix(hex, 16).ok().and_then(char::from_u32)
+                }).is_some(),
+                _ => false,
+            };
+
+            if !valid {
+                return Some(Err(TokenizeErrorReason::UnrecognizedEscaped(esc)));
+            }
+
+            let skip = match esc {
+                'x' => 2,
+                'u' => rest.find('}').unwrap() + 1,
+                _ => 0,
+            };
+            chars = rest[skip..].chars();
+        }
+
+        if chars.next() != Some('\'') {
+            return Some(Err(TokenizeErrorReason::UnexpectedPostfix));
+        }
+
+        let val = &body[..body.len() - chars.as_str().len() - 1];
+
+        let span = Span {
+            from,
+            end: from + val.len() + 2,
+        };
+
+        Some(Ok(Token::new("char_lit", val, span)))
+    }
+
+    #[inline]
+    pub fn lit_regex_m(source: &str, from: usize) -> Option<TokenMatchResult> {
+        aux_strlike_m(source, from, "/", "/", '\\')
+    .and_then(|res|
+        match res {
+            Ok(mut tok) => {
+                lazy_static::lazy_static! {
+                    pub static ref ALPHABET__: HashSet<char> = ('a'..='z').chain('A'..='Z').collect();
+                }
+
+                let mut tokv = tok.value_string();
+                if tokv.is_empty() {  // It' maybe slash comment
+                    return None;
+                }
+
+                tokv.insert(0, '/');
+                tokv.push('/');
+
+                if let Some(nxtc) = source[tok.span_len()..].chars().next() {
+                    if ALPHABET__.contains(&nxtc) {
+                        tokv.push(nxtc);
+
+                        let span = Span {
+                            from,
+                            end: from + tok.span_len() + nxtc.to_string().len(),
+                        };
+
+                        tok = tok.with_span(span);
+                    }
+                }
+
+                tok.value = str2sym(&tokv);
+
+                Some(Ok(tok.rename("lit_regex")))
+            },
+            // no closing slash, not a regex
+            Err(_) => None,
+        }
+    )
+    }
+
+
+    /// Names of the tokens which can end an expression, a `/` after them is
+    /// a division.
+    pub const EXPR_END_NAMES: &[&str] = &[
+        "id", "number", "lit_int", "lit_float", "char_lit", "sqstr", "dqstr",
+        "aqstr", "lit_regex", "rparen", "rbracket", "rbrace",
+    ];
+
+    /// Disambiguate `/` by the previous significant (non-trivia) token like
+    /// JavaScript: regex literal (`lit_regex`) at the start or after a token
+    /// which can't end an expression (`(`, `=`, `,`, operators ...),
+    /// else division (`div`).
+    pub fn regex_or_div(
+        prev: Option<&Token>,
+        source: &str,
+        from: usize,
+    ) -> Option<TokenMatchResult> {
+        if !source.starts_with('/') {
+            return None;
+        }
+
+        match prev {
+            Some(prev) if prev.check_names_in(EXPR_END_NAMES) => {
+                Some(Ok(Token::new("div", "/", Span { from, end: from + 1 })))
+            }
+            _ => lit_regex_m(source, from),
+        }
+    }
+
+    /// Line comment from `prefix` to the end of line (line break excluded),
+    /// use it in the rules as `name => @line_comment_m("#")`
+    pub fn line_comment_m<'a>(
+        prefix: &'a str,
+    ) -> impl Fn(&str, usize) -> Option<TokenMatchResult> + 'a {
+        debug_assert!(!prefix.is_empty());
+
+        move |source, from| {
+            if !source.starts_with(prefix) {
+                return None;
+            }
+
+            let len = source.find('\n').unwrap_or(source.len());
+
+            let span = Span { from, end: from + len };
+
+            Some(Ok(Token::new("line_comment", &source[..len], span)))
+        }
+    }
+
+    /// Drop the `#!...` line at the start of the source (line break kept),
+    /// it produces no token
+    pub fn shebang_m(source: &str, from: usize) -> Option<TokenMatchResult> {
+        shebang_tok_m(source, from)
+            .map(|res| res.map(|tok| Token::skip(tok.span)))
+    }
+
+    /// `shebang_m` emitting the line as a `shebang` token
+    pub fn shebang_tok_m(
+        source: &str,
+        from: usize,
+    ) -> Option<TokenMatchResult> {
+        if from != 0 || !source.starts_with("#!") {
+            return None;
+        }
+
+        let len = source.find('\n').unwrap_or(source.len());
+        let span = Span { from, end: from + len };
+
+        Some(Ok(Token::new("shebang", &source[..len], span)))
+    }
+
+    /// Block comment from `open` to the first `close` (not nested) crossing
+    /// lines, use it in the rules as `name => @block_comment_m("/*", "*/")`
+    ///
+    /// Unterminated comment is an `Unterminated` error.
+    pub fn block_comment_m<'a>(
+        open: &'a str,
+        close: &'a str,
+    ) -> impl Fn(&str, usize) -> Option<TokenMatchResult> + 'a {
+        debug_assert!(!open.is_empty());
+        debug_assert!(!close.is_empty());
+
+        move |source, from| {
+            let body = source.strip_prefix(open)?;
+
+            let len = match body.find(close) {
+                Some(i) => open.len() + i + close.len(),
+                None => return Some(Err(TokenizeErrorReason::Unterminated)),
+            };
+
+            let span = Span { from, end: from + len };
+
+            Some(Ok(Token::new("block_comment", &source[..len], span)))
+        }
+    }
+
+    /// `number` token is hexadecimal (`0xFF`)
+    pub const FLAG_HEX: u32 = 1;
+    /// `number` token is a float (`1.5`, `1e3`)
+    pub const FLAG_FLOAT: u32 = 1 << 1;
+    /// `float` literal can't fit an `f64` (`1e400`)
+    pub const FLAG_OUT_OF_RANGE: u32 = 1 << 2;
+    /// `id` mixes Latin, Greek and Cyrillic letters, see `is_confusable`
+    pub const FLAG_CONFUSABLE: u32 = 1 << 3;
+    /// heredoc with a quoted terminator (`<<'EOF'`, `<<"EOF"`), the body
+    /// is taken as is, no escape or expansion
+    pub const FLAG_RAW: u32 = 1 << 4;
+
+    /// Unsigned number literal: `0xFF`, `42`, `1.5`, `1e-3`,
+    /// the sign is left to the parser (`a-1` is `a`, `-`, `1`)
+    pub fn number_m(source: &str, from: usize) -> Option<TokenMatchResult> {
+        lazy_static::lazy_static! {
+            static ref NUMBER_REG: Regex = Regex::new(
+                r"^(?:(0[xX][[:xdigit:]]+)|[0-9]+(\.[0-9]+)?([eE][+-]?[0-9]+)?)"
+            ).unwrap();
+        }
+
+        let cap = NUMBER_REG.captures(source)?;
+        let mat = cap.get(0).unwrap().as_str();
+        let mut flags = 0;
+
+        if cap.get(1).is_some() {
+            flags |= FLAG_HEX;
+        }
+        if cap.get(2).is_some() || cap.get(3).is_some() {
+            flags |= FLAG_FLOAT;
+        }
+
+        let span = Span {
+            from,
+            end: from + mat.len(),
+        };
+
+        Some(Ok(Token::new("number", mat, span).with_flag(flags)))
+    }
+
+    /// `number_m`, with `signed` the literal may start with `+` / `-`
+    /// (`-1`), use it in the rules as `name => @number_sign_m(true)` where no
+    /// binary operator can precede a number (e.g. a data file)
+    ///
+    /// Prefer it to a hand-written sign like `[+|-]?`, which also matches
+    /// `|` (see `check_pattern`).
+    pub fn number_sign_m(
+        signed: bool,
+    ) -> impl Fn(&str, usize) -> Option<TokenMatchResult> {
+        move |source, from| {
+            let sign_len = match source.as_bytes().first() {
+                Some(b'+' | b'-') if signed => 1,
+                _ => 0,
+            };
+
+            let tok = match number_m(&source[sign_len..], from + sign_len)? {
+                Ok(tok) => tok,
+                err => return Some(err),
+            };
+
+            if sign_len == 0 {
+                return Some(Ok(tok));
+            }
+
+            let span = Span {
+                from,
+                end: tok.span.end,
+            };
+
+            Some(Ok(Token::new("number", &source[..span.len()], span)
+                .with_flag(tok.flags)))
+        }
+    }
+
+    /// Unicode identifier (`id`): XID_Start or `_`, then XID_Continue,
+    /// flagged `FLAG_CONFUSABLE` if `is_confusable`
+    pub fn ident_m_unicode(
+        source: &str,
+        from: usize,
+    ) -> Option<TokenMatchResult> {
+        let mut chars = source.char_indices();
+
+        chars.next().filter(|(_, c)| crate::xid_start_m(*c))?;
+
+        let len = chars
+            .find(|(_, c)| !crate::xid_continue_m(*c))
+            .map_or(source.len(), |(i, _)| i);
+        let ident = &source[..len];
+        let flags = if is_confusable(ident) { FLAG_CONFUSABLE } else { 0 };
+
+        let span = Span {
+            from,
+            end: from + len,
+        };
+
+        Some(Ok(Token::new("id", ident, span).with_flag(flags)))
+    }
+
+    /// Mixed-script heuristic for homoglyphs: letters from more than one of
+    /// Latin, Greek and Cyrillic (e.g. Cyrillic `а` in a Latin `pаypal`)
+    pub fn is_confusable(ident: &str) -> bool {
+        let mut scripts = ident.chars().filter_map(|c| match c as u32 {
+            0x41..=0x5A | 0x61..=0x7A | 0xC0..=0x24F => Some(0), // Latin
+            0x370..=0x3FF => Some(1),                            // Greek
+            0x400..=0x52F => Some(2),                            // Cyrillic
+            _ => None,
+        });
+
+        match scripts.next() {
+            Some(first) => scripts.any(|script| script != first),
+            None => false,
+        }
+    }
+
+    /// Forms accepted by `float_m` besides decimal `1.5`, `1e-3`
+    #[derive(Clone, Copy, Debug)]
+    pub struct FloatForms {
+        /// `NaN`, `Infinity`
+        pub special: bool,
+        /// `0x1.8p3`
+        pub hex: bool,
+    }
+
+    impl Default for FloatForms {
+        fn default() -> Self {
+            Self {
+                special: true,
+                hex: true,
+            }
+        }
+    }
+
+    /// Unsigned float literal (`float`, flagged `FLAG_FLOAT`), use it in the
+    /// rules as `name => @float_m(FloatForms::default())`
+    ///
+    /// The value is normalized: `NaN`, `Infinity` or the shortest form
+    /// of the `f64` (`0x1.8p3` -> `12.0`), out of range literal is flagged
+    /// `FLAG_OUT_OF_RANGE` and keeps its text.
+    pub fn float_m(
+        forms: FloatForms,
+    ) -> impl Fn(&str, usize) -> Option<TokenMatchResult> {
+        lazy_static::lazy_static! {
+            static ref SPECIAL_REG: Regex =
+                Regex::new(r"^(?:NaN|Infinity)\b").unwrap();
+            static ref HEX_FLOAT_REG: Regex = Regex::new(
+                r"^0[xX]([[:xdigit:]]+)(?:\.([[:xdigit:]]*))?[pP]([+-]?[0-9]+)"
+            ).unwrap();
+            static ref DEC_FLOAT_REG: Regex = Regex::new(
+                r"^[0-9]+(?:\.[0-9]+(?:[eE][+-]?[0-9]+)?|[eE][+-]?[0-9]+)"
+            ).unwrap();
+        }
+
+        move |source, from| {
+            let mut flags = FLAG_FLOAT;
+
+            let (mat, val) = if let Some(mat) = SPECIAL_REG
+                .find(source)
+                .filter(|_| forms.special)
+            {
+                (mat.as_str(), mat.as_str().to_owned())
+            } else if let Some(cap) = HEX_FLOAT_REG
+                .captures(source)
+                .filter(|_| forms.hex)
+            {
+                flags |= FLAG_HEX;
+
+                let int = cap.get(1).unwrap().as_str();
+                let frac = cap.get(2).map_or("", |m| m.as_str());
+                let exp = cap[3].parse::<i32>().unwrap_or(i32::MAX);
+
+                let mantissa = int
+                    .chars()
+                    .chain(frac.chars())
+                    .fold(0f64, |acc, c| {
+                        acc * 16.0 + c.to_digit(16).unwrap() as f64
+                    });
+                let v = mantissa
+                    * 2f64.powi(exp.saturating_sub(4 * frac.len() as i32));
+
+                (cap.get(0).unwrap().as_str(), format!("{v:?}"))
+            } else {
+                let mat = DEC_FLOAT_REG.find(source)?.as_str();
+
+                (mat, format!("{:?}", mat.parse::<f64>().unwrap()))
+            };
+
+            let val = if val == "inf" {
+                flags |= FLAG_OUT_OF_RANGE;
+                mat.to_owned()
+            } else {
+                val
+            };
+
+            let span = Span {
+                from,
+                end: from + mat.len(),
+            };
+
+            Some(Ok(Token::new("float", &val, span).with_flag(flags)))
+        }
+    }
+
+    /// handle this heredoc:
+    ///
+    /// The terminator may be quoted (`<<'EOF'`, `<<"EOF"`) and closed by the
+    /// bare `EOF`, such heredoc is flagged `FLAG_RAW`.
+    pub fn heredoc_m(
+        source: &str,
+        from: usize,
+    ) -> Option<Result<Token, TokenizeErrorReason>> {
+        lazy_static::lazy_static! {
+            pub static ref HEREDOC_2_REG_EH: RegexEh = RegexEh::new(
+                concat!(
+                    r#"^(<<<|<<-|<<|<-)[[:blank:]]*"#,
+                    r#"(?:'([^'\n]+)'|"([^"\n]+)"|([^'"\s].*))"#,
+                    r#"([[:blank:]]+.*\n|\n)([\s|\S]*?)\n(?:\2|\3|\4)"#
+                )
+            ).unwrap();
+        }
+
+        let cap_opt = HEREDOC_2_REG_EH.captures(source).unwrap();
+
+        if let Some(cap) = cap_opt {
+            let bytes_len = cap.get(0).unwrap().as_str().len();
+            let span = Span {
+                from,
+                end: from + bytes_len,
+            };
+
+            let value = cap.get(6).unwrap().as_str();
+            let quoted = cap.get(2).is_some() || cap.get(3).is_some();
+            let flags = if quoted { FLAG_RAW } else { 0 };
+
+            Some(Ok(Token::new("__aux_tmp", value, span).with_flag(flags)))
+        } else {
+            None
+        }
+    }
+
+    use crate as m6lexerkit;
+
+    make_token_matcher_rules! {
+        // Comment
+        sharp_line_comment  => r"#.*",
+
+        // White characters
+        sp      => "[[:blank:]]+",
+        newline => r#"\n\r?"#,
+
+        // Bracket
+        lparen => r"\(",
+        rparen => r"\)",
+        lbracket => r"\[",
+        rbracket => r"\]",
+        lbrace => r"\{",
+        rbrace => r"\}",
+
+        // Delimiter
+        colon => ":",
+        question => r"\?",
+        rarrow => "->",
+        rdarrow  => "=>",
+        semi   => ";",
+        comma  => ",",
+
+        // Assign
+        assign => "=",
+
+        // Unary Operation
+        inc => r"\+\+",
+        dec => r"--",
+        not => "!",
+
+        // Binary Operation
+        sub    => "-",
+        add    => r"\+" / r"[^\+]|$",
+        mul    => r"\*",
+        div    => "/",
+        dot    => r"\.",
+        ge     => ">=",
+        le     => "<=",
+        lt     => "<",
+        gt     => ">",
+        neq    => "!=",
+        eq     => "==",
+        percent=> "%",
+        and    => "&&",
+        or     => r"\|\|"
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+//// Tokenizer2
+
+////////////////////////////////////////////////////////////////////////////////
+//// Char Matcher (used for string splitter)
+
+pub type BoxCharMatcher = Box<dyn CharMatcher + Send + Sync>;
+
+pub trait CharMatcher {
+    fn is_match(&self, c: char) -> bool;
+
+    fn and<M>(self, other: M) -> BoxCharMatcher
+    where
+        Self: Sized + Send + Sync + 'static,
+        M: CharMatcher + Send + Sync + 'static,
+    {
+        Box::new(AndCharMatcher::new(Box::new(self), Box::new(other)))
+    }
+
+    fn or<M>(self, other: M) -> BoxCharMatcher
+    where
+        Self: Sized + Send + Sync + 'static,
+        M: CharMatcher + Send + Sync + 'static,
+    {
+        Box::new(OrCharMatcher::new(Box::new(self), Box::new(other)))
+    }
+
+    fn not(self) -> BoxCharMatcher
+    where
+        Self: Sized + Send + Sync + 'static,
+    {
+        Box::new(NotCharMatcher::new(Box::new(self)))
+    }
+}
+
+impl CharMatcher for BoxCharMatcher {
+    #[inline]
+    fn is_match(&self, c: char) -> bool {
+        self.as_ref().is_match(c)
+    }
+}
+
+/// Simple Char Matcher
+pub struct SimpleCharMatcher {
+    target: char,
+}
+
+/// `SimpleCharMatcher::try_new` of an empty target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmptyTargetError;
+
+impl fmt::Display for EmptyTargetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SimpleCharMatcher: empty target")
+    }
+}
+
+impl std::error::Error for EmptyTargetError {}
+
+impl SimpleCharMatcher {
+    /// Match the first char of `s` (the rest is ignored), panic if `s` is empty
+    pub fn new(s: &str) -> Self {
+        Self::try_new(s).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// `new` failing on an empty `s` instead of panicking
+    pub fn try_new(s: &str) -> Result<Self, EmptyTargetError> {
+        s.chars().next().map(Self::from_char).ok_or(EmptyTargetError)
+    }
+
+    /// Match exactly `c`
+    pub fn from_char(c: char) -> Self {
+        Self { target: c }
+    }
+}
+
+impl CharMatcher for SimpleCharMatcher {
+    #[inline]
+    fn is_match(&self, c: char) -> bool {
+        self.target == c
+    }
+}
+
+pub struct RegexCharMatcher {
+    pat: Regex,
+    /// precomputed match result for ASCII chars
+    ascii: [bool; 128],
+}
+
+impl RegexCharMatcher {
+    pub fn new(patstr: &str) -> Self {
+        let pat = Regex::new(patstr).unwrap();
+        let mut ascii = [false; 128];
+        let mut buf = [0u8; 4];
+
+        for (i, res) in ascii.iter_mut().enumerate() {
+            *res = pat.is_match((i as u8 as char).encode_utf8(&mut buf));
+        }
+
+        Self { pat, ascii }
+    }
+}
+
+impl CharMatcher for RegexCharMatcher {
+    #[inline]
+    fn is_match(&self, c: char) -> bool {
+        if c.is_ascii() {
+            return self.ascii[c as usize];
+        }
+
+        let mut buf = [0u8; 4];
+        self.pat.is_match(c.encode_utf8(&mut buf))
+    }
+}
+
+/// Match if both matchers match
+pub struct AndCharMatcher {
+    lhs: BoxCharMatcher,
+    rhs: BoxCharMatcher,
+}
+
+impl AndCharMatcher {
+    pub fn new(lhs: BoxCharMatcher, rhs: BoxCharMatcher) -> Self {
+        Self { lhs, rhs }
+    }
+}
+
+impl CharMatcher for AndCharMatcher {
+    #[inline]
+    fn is_match(&self, c: char) -> bool {
+        self.lhs.is_match(c) && self.rhs.is_match(c)
+    }
+}
+
+/// Match if either matcher matches
+pub struct OrCharMatcher {
+    lhs: BoxCharMatcher,
+    rhs: BoxCharMatcher,
+}
+
+impl OrCharMatcher {
+    pub fn new(lhs: BoxCharMatcher, rhs: BoxCharMatcher) -> Self {
+        Self { lhs, rhs }
+    }
+}
+
+impl CharMatcher for OrCharMatcher {
+    #[inline]
+    fn is_match(&self, c: char) -> bool {
+        self.lhs.is_match(c) || self.rhs.is_match(c)
+    }
+}
+
+/// Match if the inner matcher doesn't match
+pub struct NotCharMatcher {
+    inner: BoxCharMatcher,
+}
+
+impl NotCharMatcher {
+    pub fn new(inner: BoxCharMatcher) -> Self {
+        Self { inner }
+    }
+}
+
+impl CharMatcher for NotCharMatcher {
+    #[inline]
+    fn is_match(&self, c: char) -> bool {
+        !self.inner.is_match(c)
+    }
+}
+
+pub type FnCharMatcher = fn(char) -> bool;
+
+/// Unicode alphabetic, used as `u_alpha` in `lexdfamap!`
+#[inline]
+pub fn u_alpha_m(c: char) -> bool {
+    c.is_alphabetic()
+}
+
+/// Unicode alphanumeric, used as `u_alnum` in `lexdfamap!`
+#[inline]
+pub fn u_alnum_m(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+/// Unicode whitespace, used as `u_space` in `lexdfamap!`
+#[inline]
+pub fn u_space_m(c: char) -> bool {
+    c.is_whitespace()
+}
+
+/// Unicode identifier head (XID_Start or `_`), used as `xid_start`
+#[inline]
+pub fn xid_start_m(c: char) -> bool {
+    c == '_' || unicode_ident::is_xid_start(c)
+}
+
+/// Unicode identifier body (XID_Continue), used as `xid_continue`
+#[inline]
+pub fn xid_continue_m(c: char) -> bool {
+    unicode_ident::is_xid_continue(c)
+}
+/// state => [(matcher, (next state, token end, token name))],
+/// the token name, if any, names the token being read from the transition on
+pub type LexDFAMap =
+    HashMap<Symbol, Vec<(FnCharMatcher, (Symbol, bool, Option<Symbol>))>>;
+
+#[allow(unused)]
+pub const ENTRY_ST: &str = "Entry";
+
+pub struct LexDFA<'a> {
+    map: &'a LexDFAMap,
+    st: Symbol,
+    /// name of the token being read, given by the transitions
+    tok_name: Option<Symbol>,
+}
+
+impl<'a> LexDFA<'a> {
+    pub fn new(map: &'a LexDFAMap) -> Self {
+        Self {
+            map,
+            st: str2sym(ENTRY_ST),
+            tok_name: None,
+        }
+    }
+
+    /// Name of the token being read (take it before the `forward` ending it),
+    /// `None` leaves it to the recognizer
+    pub fn tok_name(&self) -> Option<Symbol> {
+        self.tok_name
+    }
+
+    // Token END?
+    pub fn forward(&mut self, ch: char) -> Result<bool, TokenizeErrorReason> {
+        if let Some(items) = self.map.get(&self.st) {
+            for (matcher, (sym, res, name)) in items.iter() {
+                if matcher(ch) {
+                    self.st = *sym;
+
+                    if *res {
+                        self.tok_name = None;
+                    }
+                    if name.is_some() {
+                        self.tok_name = *name;
+                    }
+
+                    return Ok(*res);
+                }
+            }
+        }
+
+        Err(TokenizeErrorReason::UncoveredChar(ch))
+    }
+}
+
+#[macro_export]
+macro_rules! declare_st {
+    ( $($name:ident),* ) => {
+        use $crate::concat_idents2;
+
+        $(
+            concat_idents2!(state_name = $name, _ST {
+                const state_name: &'static str = stringify!($name);
+            });
+        )*
+
+        /// All declared states (`ENTRY_ST` excluded)
+        pub const ALL_STATES: &[&str] = &[$(stringify!($name)),*];
+    };
+}
+
+
+/// `state => { matcher | next_state, token_end ... }`, a transition may name
+/// the token being read as `matcher | next_state, token_end => name`
+///
+/// A state key written twice is a compile error, two keys of the same value
+/// (e.g. two consts) panic when the map is built.
+#[macro_export]
+macro_rules! lexdfamap {
+    ( $($cur_st:expr =>
+        {
+            $( $matcher:ident | $nxt_st:expr, $flag:literal $(=> $tok_name:ident)? )*
+        }
+      ),*
+    ) => {
+        {
+            use std::collections::HashMap;
+            use $crate::FnCharMatcher;
+            use $crate::concat_idents2;
+            use $crate::str2sym;
+
+            // the same key written twice is a compile error,
+            // different keys of the same value panic below
+            $crate::__check_dup_states!($($cur_st),*);
+
+            let mut _map: $crate::LexDFAMap = HashMap::new();
+
+            $(
+                let mut trans_vec = Vec::new();
+
+                $(
+                    let nxt_st = str2sym($nxt_st);
+                    let tok_name = None $( .or(Some(str2sym(stringify!($tok_name)))) )?;
+
+                    trans_vec.push((
+                        concat_idents2!(matcher_name = $matcher, _m {
+                            matcher_name as FnCharMatcher
+                        }),
+                        (nxt_st, $flag, tok_name)
+                    ));
+                )*
+
+                let cur_st = str2sym($cur_st);
+
+                assert!(
+                    _map.insert(cur_st, trans_vec).is_none(),
+                    "lexdfamap!: duplicated state key {:?}",
+                    $cur_st
+                );
+            )*
+
+            _map
+        }
+    }
+}
+
+
+/// Built by `new` (or `token_recognizer!`), the patterns are fixed after it
+pub struct TokenRecognizer {
+    pub lookhead: usize,
+    pat_items: Vec<(Regex, Symbol)>,
+    /// all patterns of `pat_items` matched in one pass, None if the set
+    /// exceeds the regex size limit (the patterns are tried one by one)
+    pat_set: Option<RegexSet>,
+}
+
+impl TokenRecognizer {
+    pub fn new(lookhead: usize, pat_items: Vec<(Regex, Symbol)>) -> Self {
+        let pat_set =
+            RegexSet::new(pat_items.iter().map(|(pat, _)| pat.as_str())).ok();
+
+        Self {
+            lookhead,
+            pat_items,
+            pat_set,
+        }
+    }
+
+    /// (pattern, token name) in the priority order
+    pub fn pat_items(&self) -> &[(Regex, Symbol)] {
+        &self.pat_items
+    }
+
+    pub fn recognize(&self, source: &str, span: Span) -> Option<Token> {
+        let mut end = min(span.end, span.from + self.lookhead);
+
+        // lookhead is counted by bytes, extend it to the char boundary
+        while !source.is_char_boundary(end) {
+            end += 1;
+        }
+
+        let text = &source[span.from..end];
+
+        let name = if let Some(pat_set) = &self.pat_set {
+            pat_set
+                .matches(text)
+                .iter()
+                .next()
+                .map(|i| self.pat_items[i].1)
+        } else {
+            self.pat_items
+                .iter()
+                .find(|(pat, _)| pat.is_match(text))
+                .map(|(_, name)| *name)
+        };
+
+        let value = str2sym(&source[span.from..span.end]);
+
+        name.map(|name| Token::from_parts(name, value, span))
+    }
+}
+
+
+#[macro_export]
+macro_rules! token_recognizer {
+    ( $lookahead:literal | $($token_name:ident => $patstr:literal),* | ) => {
+        {
+            use $crate::Regex;
+            use $crate::str2sym;
+            use $crate::TokenRecognizer;
+
+            let mut pat_items = vec![];
+
+            $(
+                let mut patstr = $patstr.to_owned();
+
+                if !patstr.starts_with('^') {
+                    patstr.insert(0, '^')
+                }
+
+                pat_items.push((
+                    Regex::new(&patstr).unwrap(),
+                    str2sym(stringify!($token_name))
+                ));
+            )*
+
+            TokenRecognizer::new($lookahead, pat_items)
+        }
+    }
+}
+
+
+
+pub fn tokenize2(
+    srcfile: &SrcFileInfo,
+    dfamap: &LexDFAMap,
+    reconizer: &TokenRecognizer,
+) -> TokenizeResult {
+    let source = srcfile.get_srcstr();
+    let errsrc = ErrorSrc::new(srcfile);
+    let mut tokens = vec![];
+
+    if source.is_empty() {
+        return Ok(tokens);
+    }
+
+    let mut dfa = LexDFA::new(dfamap);
+    let mut bytes_pos = 0;
+    let mut chars_pos = 0usize; // chars offset of the cache start
+    let mut cache = String::new();
+    let mut cache_chars = 0usize;
+
+    // named by the DFA or else by the recognizer
+    let recognize = |name: Option<Symbol>, span: Span, start, char_len| {
+        let tok = match name {
+            Some(name) => {
+                let value = str2sym(&source[span.from..span.end]);
+
+                Some(Token::from_parts(name, value, span))
+            }
+            None => reconizer.recognize(source, span),
+        };
+
+        tok.map(|tok| Token {
+            char_from: Some(start),
+            char_len: Some(char_len),
+            ..tok
+        })
+        .ok_or_else(|| {
+            errsrc.error(TokenizeErrorReason::UnrecognizedToken, start)
+        })
+    };
+
+    for c in source.chars() {
+        let tok_name = dfa.tok_name();
+        let tok_end = dfa
+            .forward(c)
+            .map_err(|reason| errsrc.error(reason, chars_pos + cache_chars))?;
+
+        if tok_end {
+            // REACH TOKEN END
+            // recognize token
+            let span = Span {
+                from: bytes_pos,
+                end: bytes_pos + cache.len(),
+            };
+
+            tokens.push(recognize(tok_name, span, chars_pos, cache_chars)?);
+
+            bytes_pos += span.len();
+            chars_pos += cache_chars;
+            cache.clear();
+            cache_chars = 0;
+        }
+
+        cache.push(c);
+        cache_chars += 1;
+    }
+
+    // Flush the pending token at EOF
+    if !cache.is_empty() {
+        let span = Span {
+            from: bytes_pos,
+            end: bytes_pos + cache.len(),
+        };
+
+        tokens.push(recognize(dfa.tok_name(), span, chars_pos, cache_chars)?);
+    }
+
+    Ok(tokens)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        self as m6lexerkit, make_char_matcher_rules, tokenize2, SrcFileInfo,
+        Span, TokenizeErrorReason, ENTRY_ST,
+    };
+
+    make_char_matcher_rules! {
+        ident      => "[[:alnum:]_]" | r,
+        ident_head => "[[:alpha:]_]" | r,
+        num        => "[[:digit:]]"  | r,
+        sp         => "[[:space:]]"  | r
+    }
+
+    declare_st! {
+        BLANK,
+        IDENT_NAME,
+        NUM
+    }
+
+    fn tokenize2_ident(source: &str) -> super::TokenizeResult {
+        let dfamap = lexdfamap! {
+            ENTRY_ST => {
+                sp         | BLANK_ST,      false
+                ident_head | IDENT_NAME_ST, false
+                num        | NUM_ST,        false
+            },
+            BLANK_ST => {
+                sp         | BLANK_ST,      false
+                ident_head | IDENT_NAME_ST, true
+                num        | NUM_ST,        true
+            },
+            IDENT_NAME_ST => {
+                ident      | IDENT_NAME_ST, false
+                sp         | BLANK_ST,      true
+            },
+            NUM_ST => {
+                num        | NUM_ST,        false
+                sp         | BLANK_ST,      true
+            }
+        };
+        let recognizer = token_recognizer![ 2 |
+            id => "[[:alpha:]_][[:alnum:]_]*",
+            sp => "[[:space:]]+"
+        |];
+
+        tokenize2(
+            &SrcFileInfo::from_str(source.to_owned()),
+            &dfamap,
+            &recognizer,
+        )
+    }
+
+    /// Small JS-like grammar shared by the tests
+    mod js_rules {
+        use crate::{
+            self as m6lexerkit, make_token_matcher_rules, TokenMatchResult,
+        };
+
+        make_token_matcher_rules! {
+            id      => "[[:alpha:]_][[:alnum:]_]*",
+            lit_int => "[0-9]+",
+            dqstr   => @m6lexerkit::prelude::dqstr_m,
+            aqstr   => @m6lexerkit::prelude::aqstr_m,
+
+            slash_line_comment => r"//.*",
+            sp      => "[[:blank:]]+",
+            newline => r#"\n\r?"#,
+
+            lparen   => r"\(",
+            rparen   => r"\)",
+            lbracket => r"\[",
+            rbracket => r"\]",
+            lbrace   => r"\{",
+            rbrace   => r"\}",
+
+            colon    => ":",
+            question => r"\?",
+            double_arrow => "=>",
+            semi     => ";",
+            comma    => ",",
+
+            realeq => "===",
+            assign => "=",
+            inc    => r"\+\+",
+            add    => r"\+",
+            sub    => "-",
+            mul    => r"\*",
+            dot    => r"\."
+        }
+    }
+
+    /// A few lines of `examples/es6/examples/exp0.js`
+    fn js_srcfile() -> SrcFileInfo {
+        SrcFileInfo::from_str(
+            r#"import { find } from "./find.js";
+
+// apps registered so far
+const apps = [];
+
+export function getAppStatus(appName) {
+  const app = find(apps, (app) => app.name === appName);
+  return app ? app.status : `none: ${appName}`;
+}
+"#
+            .to_owned(),
+        )
+    }
+
+    #[test]
+    fn test_error_info() {
+        println!("aaaa\n^^^^^")
+    }
+
+    #[test]
+    fn test_tokenize2_flush_eof() {
+        let tokens = tokenize2_ident("abc").unwrap();
+
+        assert_eq!(tokens.len(), 1);
+        assert!(tokens[0].check_name("id"));
+        assert!(tokens[0].check_value("abc"));
+
+        let tokens = tokenize2_ident("ab  cd").unwrap();
+
+        assert_eq!(tokens.len(), 3);
+        assert!(tokens[2].check_name("id"));
+        assert!(tokens[2].check_value("cd"));
+    }
+
+    #[test]
+    fn test_tokenize2_error_pos() {
+        let err = tokenize2_ident("ab\n  $").unwrap_err();
+
+        assert!(matches!(err.reason, TokenizeErrorReason::UncoveredChar('$')));
+        assert_eq!(err.src.offset2srcloc(err.start), SrcLoc::new((2, 3)));
+
+        // no recognizer rule for numbers
+        let err = tokenize2_ident("ab 12").unwrap_err();
+
+        assert!(matches!(err.reason, TokenizeErrorReason::UnrecognizedToken));
+        assert_eq!(err.src.offset2srcloc(err.start), SrcLoc::new((1, 4)));
+    }
+
+    #[test]
+    fn test_token_recognizer() {
+        let recognizer = token_recognizer![ 2 |
+            id      => "[[:alpha:]_][[:alnum:]_]*",
+            lit_int => "[0-9]+",
+            sp      => "[[:space:]]+"
+        |];
+        let source = "ab 12 1a";
+        let span = |from, end| Span { from, end };
+
+        let tok = recognizer.recognize(source, span(0, 2)).unwrap();
+        assert!(tok.check_name("id"));
+
+        let tok = recognizer.recognize(source, span(2, 3)).unwrap();
+        assert!(tok.check_name("sp"));
+
+        let tok = recognizer.recognize(source, span(3, 5)).unwrap();
+        assert!(tok.check_name("lit_int"));
+        assert!(tok.check_value("12"));
+
+        // anchored: `id` must not match the `a` after the digit
+        let tok = recognizer.recognize(source, span(6, 8)).unwrap();
+        assert!(tok.check_name("lit_int"));
+
+        assert!(recognizer.recognize("$", span(0, 1)).is_none());
+    }
+
+    #[test]
+    fn test_char_matcher_combinators() {
+        let alpha_but_x = RegexCharMatcher::new("[[:alpha:]]")
+            .and(SimpleCharMatcher::new("x").not());
+
+        assert!(alpha_but_x.is_match('a'));
+        assert!(!alpha_but_x.is_match('x'));
+        assert!(!alpha_but_x.is_match('1'));
+
+        let alpha_or_digit = RegexCharMatcher::new("[[:alpha:]]")
+            .or(RegexCharMatcher::new("[[:digit:]]"));
+
+        assert!(alpha_or_digit.is_match('x'));
+        assert!(alpha_or_digit.is_match('1'));
+        assert!(!alpha_or_digit.is_match('_'));
+
+        // combinators chain on the boxed result
+        let alnum_but_x = alpha_or_digit.and(SimpleCharMatcher::new("x").not());
+
+        assert!(alnum_but_x.is_match('1'));
+        assert!(!alnum_but_x.is_match('x'));
+    }
+
+    #[test]
+    fn test_unicode_char_matchers() {
+        use crate::{u_alpha_m, u_space_m, xid_continue_m, xid_start_m};
+
+        assert!(u_alpha_m('é') && u_alpha_m('変'));
+        assert!(u_space_m('\u{3000}'));
+        assert!(xid_start_m('_') && !xid_start_m('1'));
+        assert!(xid_continue_m('1'));
+
+        let dfamap = lexdfamap! {
+            ENTRY_ST => {
+                u_space      | BLANK_ST,      false
+                xid_start    | IDENT_NAME_ST, false
+            },
+            BLANK_ST => {
+                u_space      | BLANK_ST,      false
+                xid_start    | IDENT_NAME_ST, true
+            },
+            IDENT_NAME_ST => {
+                xid_continue | IDENT_NAME_ST, false
+                u_space      | BLANK_ST,      true
+            }
+        };
+        let recognizer = token_recognizer![ 4 |
+            id => r"[_\p{XID_Start}]\p{XID_Continue}*",
+            sp => r"\s+"
+        |];
+        let srcfile = SrcFileInfo::from_str("café 変数".to_owned());
+        let tokens = tokenize2(&srcfile, &dfamap, &recognizer).unwrap();
+
+        assert_eq!(tokens.len(), 3);
+        assert!(tokens[0].check_name("id") && tokens[0].check_value("café"));
+        assert!(tokens[2].check_name("id") && tokens[2].check_value("変数"));
+    }
+
+    #[test]
+    fn test_regex_char_matcher() {
+        let matcher = RegexCharMatcher::new(r"[[:alpha:]_\p{Han}]");
+
+        assert!(matcher.is_match('a'));
+        assert!(matcher.is_match('_'));
+        assert!(!matcher.is_match('1'));
+        assert!(!matcher.is_match('\0'));
+        assert!(matcher.is_match('変'));
+        assert!(!matcher.is_match('é'));
+    }
+
+    #[test]
+    fn test_compiled_regex_shared() {
+        use std::sync::Arc;
+
+        use crate::TokenMatcher;
+
+        let lhs = TokenMatcher::new("^(shared_pat)", "lhs");
+        let rhs = TokenMatcher::new("^(shared_pat)", "rhs");
+
+        assert!(Arc::ptr_eq(&lhs.pat, &rhs.pat));
+
+        let other = TokenMatcher::new("^(other_pat)", "other");
+
+        assert!(!Arc::ptr_eq(&lhs.pat, &other.pat));
+
+        assert!(crate::compiled_regex("^(").is_err());
+        // runtime patterns stay out of the registry
+        let runtime = TokenMatcher::try_new("^(shared_pat)", "rt").unwrap();
+        assert!(!Arc::ptr_eq(&lhs.pat, &runtime.pat));
+        assert!(runtime.id.is_none());
+    }
+
+    mod path_rules {
+        use crate::{
+            self as m6lexerkit, make_token_matcher_rules, TokenMatchResult,
+        };
+
+        make_token_matcher_rules! {
+            id     => "[[:alpha:]_][[:alnum:]_]*",
+            string => @m6lexerkit::prelude::dqstr_m,
+            sp     => "[[:blank:]]+"
+        }
+    }
+
+    #[test]
+    fn test_token_matcher_rules_path() {
+        use crate::tokenize;
+
+        let srcfile = SrcFileInfo::from_str(r#"say "hi""#.to_owned());
+        let tokens = tokenize(&srcfile, &path_rules::MATCHERS[..]).unwrap();
+
+        assert_eq!(path_rules::MATCHERS.len(), 3);
+        assert_eq!(tokens.len(), 3);
+        assert!(tokens[0].check_name("id"));
+        assert!(tokens[2].check_name("string") && tokens[2].check_value("hi"));
+    }
+
+    #[test]
+    fn test_token_matcher_rules_enum() {
+        use path_rules::TokName;
+
+        use crate::tokenize;
+
+        let srcfile = SrcFileInfo::from_str(r#"say "hi""#.to_owned());
+        let tokens = tokenize(&srcfile, &path_rules::MATCHERS[..]).unwrap();
+        let names = tokens
+            .iter()
+            .map(|tok| tok.name_enum::<TokName>().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, [TokName::Id, TokName::Sp, TokName::String]);
+        assert_eq!(TokName::String.as_str(), "string");
+        assert!(Token::eof().name_enum::<TokName>().is_none());
+    }
+
+    #[test]
+    fn test_token_matcher_rules_names() {
+        use path_rules::{names, TOKEN_NAMES};
+
+        use crate::tokenize;
+
+        assert_eq!(TOKEN_NAMES, ["id", "string", "sp"]);
+
+        let srcfile = SrcFileInfo::from_str(r#"say "hi""#.to_owned());
+        let tokens = tokenize(&srcfile, &path_rules::MATCHERS[..])
+            .unwrap()
+            .into_iter()
+            .filter(|tok| !tok.check_name(names::SP))
+            .collect::<Vec<_>>();
+
+        assert_eq!(tokens.len(), 2);
+        assert!(tokens[0].check_name(names::ID));
+        assert!(tokens[1].check_name(names::STRING));
+    }
+
+    #[test]
+    fn test_declare_st_all_states() {
+        assert_eq!(ALL_STATES.len(), 3);
+        assert_eq!(ALL_STATES, [BLANK_ST, IDENT_NAME_ST, NUM_ST]);
+    }
+
+    #[test]
+    fn test_token_flags() {
+        use crate::prelude::{number_m, FLAG_FLOAT, FLAG_HEX};
+
+        let tok = number_m("0xFF;", 0).unwrap().unwrap();
+
+        assert!(tok.check_value("0xFF"));
+        assert_eq!(tok.span_len(), 4);
+        assert!(tok.has_flag(FLAG_HEX) && !tok.has_flag(FLAG_FLOAT));
+
+        let tok = number_m("255", 0).unwrap().unwrap();
+        assert_eq!(tok.flags, 0);
+
+        let tok = number_m("1.5e3", 0).unwrap().unwrap();
+        assert!(tok.has_flag(FLAG_FLOAT) && !tok.has_flag(FLAG_HEX));
+
+        assert!(number_m("x1", 0).is_none());
+        assert!(number_m("-1", 0).is_none());
+
+        let tok = Token::eof().with_flag(FLAG_HEX).with_flag(FLAG_FLOAT);
+        assert!(tok.has_flag(FLAG_HEX | FLAG_FLOAT));
+    }
+
+    #[test]
+    fn test_token_trivia() {
+        use crate::{set_trivia_names, tokenize};
+
+        let tokens = tokenize(&js_srcfile(), &js_rules::MATCHERS[..]).unwrap();
+        let trivia = tokens
+            .iter()
+            .filter(|tok| tok.is_trivia())
+            .collect::<Vec<_>>();
+
+        assert!(!trivia.is_empty());
+        assert!(trivia.iter().all(|tok| tok.check_names_in(&[
+            "sp",
+            "newline",
+            "slash_line_comment"
+        ])));
+        assert!(tokens.iter().any(|tok| tok.is_comment()));
+        assert!(tokens
+            .iter()
+            .filter(|tok| tok.is_comment())
+            .all(|tok| tok.value_string().starts_with("//")));
+        assert!(tokens
+            .iter()
+            .filter(|tok| !tok.is_trivia())
+            .all(|tok| !tok.check_names_in(&["sp", "newline"])));
+
+        // configurable per thread
+        set_trivia_names(&["sp"], &[]);
+        assert!(!tokens.iter().any(|tok| tok.is_comment()));
+        assert!(!tokens
+            .iter()
+            .any(|tok| tok.check_name("newline") && tok.is_trivia()));
+    }
+
+    #[test]
+    fn test_check_value_ci() {
+        use crate::Token;
+
+        let tok = Token::eof().mapval("SELECT");
+
+        assert!(tok.check_value_ci("select"));
+        assert!(tok.check_value_ci("Select"));
+        assert!(!tok.check_value("select"));
+        assert!(!tok.check_value_ci("selects"));
+        assert!(tok.check_values_in_ci(&["from", "select"]));
+        assert!(!tok.check_values_in_ci(&["from", "where"]));
+
+        let tok = tok.mapval("ÉTÉ");
+        assert!(tok.check_value_ci("été"));
+    }
+
+    #[cfg(feature = "nfc")]
+    #[test]
+    fn test_check_value_nfc() {
+        // precomposed vs decomposed `é`
+        let tok = Token::eof().mapval("caf\u{e9}");
+
+        assert!(tok.check_value_nfc("cafe\u{301}"));
+        assert!(!tok.check_value("cafe\u{301}"));
+    }
+
+    #[test]
+    fn test_token_at() {
+        use crate::prelude::token_at;
+
+        let toks = tokenize2_ident("ab  cd").unwrap();
+        let mut toks: Vec<Token> =
+            toks.into_iter().filter(|tok| !tok.is_trivia()).collect();
+        toks.push(Token::new("eof", "", Span { from: 6, end: 6 }));
+
+        // boundaries
+        assert_eq!(token_at(&toks, 0).unwrap().value_string(), "ab");
+        assert_eq!(token_at(&toks, 1).unwrap().value_string(), "ab");
+        assert_eq!(token_at(&toks, 4).unwrap().value_string(), "cd");
+        assert_eq!(token_at(&toks, 5).unwrap().value_string(), "cd");
+
+        // gap left by the trimmed blank
+        assert!(token_at(&toks, 2).is_none());
+        assert!(token_at(&toks, 3).is_none());
+
+        // zero-length EOF
+        assert!(token_at(&toks, 6).unwrap().check_name("eof"));
+        assert!(token_at(&toks, 7).is_none());
+        assert!(token_at(&[], 0).is_none());
+    }
+
+    #[test]
+    fn test_locate_tokens() {
+        let srcfile = SrcFileInfo::from_str("ab cd\n  ef".to_owned());
+        let toks = tokenize2_ident(srcfile.get_srcstr()).unwrap();
+
+        let locs = srcfile
+            .locate_tokens(&toks)
+            .filter(|(tok, _)| tok.check_name("id"))
+            .map(|(tok, loc)| (tok.value_string(), loc))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            locs,
+            vec![
+                ("ab".to_owned(), SrcLoc::new((1, 1))),
+                ("cd".to_owned(), SrcLoc::new((1, 4))),
+                ("ef".to_owned(), SrcLoc::new((2, 3))),
+            ]
+        );
+    }
+
+    mod trailing_rules {
+        use crate::{self as m6lexerkit, TokenMatchResult};
+        use proc_macros::make_token_matcher_rules;
+
+        make_token_matcher_rules! {
+            id  => "[[:alpha:]_][[:alnum:]_]*",
+            inc => r"\+\+",
+            add => r"\+" / r"[^\+]|$"
+        }
+    }
+
+    #[test]
+    fn test_token_matcher_trailing() {
+        use crate::tokenize;
+
+        let toks = tokenize(
+            &SrcFileInfo::from_str("a+b++c+".to_owned()),
+            &trailing_rules::MATCHERS[..],
+        )
+        .unwrap();
+
+        assert_eq!(
+            toks.iter()
+                .map(|tok| (tok.name_string(), tok.value_string(), tok.span))
+                .collect::<Vec<_>>(),
+            vec![
+                ("id".to_owned(), "a".to_owned(), Span { from: 0, end: 1 }),
+                ("add".to_owned(), "+".to_owned(), Span { from: 1, end: 2 }),
+                ("id".to_owned(), "b".to_owned(), Span { from: 2, end: 3 }),
+                ("inc".to_owned(), "++".to_owned(), Span { from: 3, end: 5 }),
+                ("id".to_owned(), "c".to_owned(), Span { from: 5, end: 6 }),
+                ("add".to_owned(), "+".to_owned(), Span { from: 6, end: 7 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_add_at_eof() {
+        use crate::{prelude::add_m, tokenize};
+
+        let toks = tokenize(
+            &SrcFileInfo::from_str("a+".to_owned()),
+            &js_rules::MATCHERS[..],
+        )
+        .unwrap();
+
+        assert_eq!(toks.len(), 2);
+        assert!(toks[1].check_name("add"));
+        assert_eq!(toks[1].span, Span { from: 1, end: 2 });
+
+        // the char following `+` isn't consumed
+        let tok = add_m("+b", 0).unwrap().unwrap();
+        assert_eq!((tok.value_string(), tok.span_len()), ("+".to_owned(), 1));
+        assert!(add_m("++", 0).is_none());
+    }
+
+    #[test]
+    fn test_token_matcher_groupless() {
+        use crate::TokenMatcher;
+
+        let matcher = TokenMatcher::new("^select", "kw_select");
+        let tok = matcher.fetch_tok("select *", 3).unwrap().unwrap();
+
+        assert!(tok.check_name("kw_select"));
+        assert!(tok.check_value("select"));
+        assert_eq!(tok.span, Span { from: 3, end: 9 });
+        assert!(matcher.fetch_tok("from", 0).is_none());
+
+        // optional group 1 not participating
+        let matcher = TokenMatcher::new("^-(x)?", "dash");
+        let tok = matcher.fetch_tok("-", 0).unwrap().unwrap();
+        assert!(tok.check_value(""));
+        assert_eq!(tok.span_len(), 1);
+    }
+
+    #[test]
+    fn test_tokenize_multi() {
+        use crate::{
+            tokenize_multi, MultiTokenMatchResult, MultiTokenMatcher,
+        };
+
+        fn exec_var_m(source: &str, from: usize) -> Option<MultiTokenMatchResult> {
+            let rem = source.strip_prefix('$')?;
+            let len = rem
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(rem.len());
+
+            if len == 0 {
+                return None;
+            }
+
+            Some(Ok(vec![
+                Token::new("exec_sigil", "$", Span { from, end: from + 1 }),
+                Token::new(
+                    "id",
+                    &rem[..len],
+                    Span { from: from + 1, end: from + 1 + len },
+                ),
+            ]))
+        }
+
+        fn sp_m(source: &str, from: usize) -> Option<MultiTokenMatchResult> {
+            let len = source.len() - source.trim_start().len();
+
+            let span = Span { from, end: from + len };
+
+            (len > 0).then(|| Ok(vec![Token::new("sp", &source[..len], span)]))
+        }
+
+        let matchers = [exec_var_m as MultiTokenMatcher, sp_m];
+        let toks = tokenize_multi(
+            &SrcFileInfo::from_str("$foo $bar".to_owned()),
+            &matchers,
+        )
+        .unwrap();
+
+        assert_eq!(
+            toks.iter()
+                .map(|tok| (tok.name_string(), tok.span))
+                .collect::<Vec<_>>(),
+            vec![
+                ("exec_sigil".to_owned(), Span { from: 0, end: 1 }),
+                ("id".to_owned(), Span { from: 1, end: 4 }),
+                ("sp".to_owned(), Span { from: 4, end: 5 }),
+                ("exec_sigil".to_owned(), Span { from: 5, end: 6 }),
+                ("id".to_owned(), Span { from: 6, end: 9 }),
+            ]
+        );
+        assert!(toks[1].check_value("foo"));
+
+        // checked in release builds too: a gap, a span off the position
+        fn gap_m(source: &str, from: usize) -> Option<MultiTokenMatchResult> {
+            source.strip_prefix('#')?;
+
+            Some(Ok(vec![
+                Token::new("sharp", "#", Span { from, end: from + 1 }),
+                Token::new(
+                    "sharp",
+                    "#",
+                    Span { from: from + 2, end: from + 3 },
+                ),
+            ]))
+        }
+
+        fn shifted_m(
+            source: &str,
+            _from: usize,
+        ) -> Option<MultiTokenMatchResult> {
+            source.strip_prefix('@')?;
+
+            Some(Ok(vec![Token::new("at", "@", Span { from: 0, end: 1 })]))
+        }
+
+        for source in ["$a ###", "$a @"] {
+            let err = tokenize_multi(
+                &SrcFileInfo::from_str(source.to_owned()),
+                &[exec_var_m as MultiTokenMatcher, sp_m, gap_m, shifted_m],
+            )
+            .unwrap_err();
+
+            assert!(
+                matches!(err.reason, TokenizeErrorReason::MisplacedSpan(_)),
+                "{source}"
+            );
+            assert_eq!(err.start, 3);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_bounded() {
+        use crate::{tokenize_bounded, TokenizeLimits};
+
+        let srcfile = SrcFileInfo::from_str("a + b + c".to_owned());
+        let bounded = |max_tokens, max_bytes| {
+            tokenize_bounded(
+                &srcfile,
+                &js_rules::MATCHERS[..],
+                TokenizeLimits { max_tokens, max_bytes },
+            )
+        };
+
+        assert_eq!(bounded(9, 9).unwrap().len(), 9);
+
+        let err = bounded(4, usize::MAX).unwrap_err();
+        assert!(matches!(err.reason, TokenizeErrorReason::LimitExceeded));
+        assert_eq!(err.start, 4);
+
+        let err = bounded(usize::MAX, 8).unwrap_err();
+        assert!(matches!(err.reason, TokenizeErrorReason::LimitExceeded));
+    }
+
+    #[test]
+    fn test_tokenize_zero_advance() {
+        use crate::{str2sym, tokenize, FnMatcher, TokenMatchResult};
+
+        // broken: span relative to the slice instead of the whole source
+        fn broken_m(source: &str, _from: usize) -> Option<TokenMatchResult> {
+            let len = source.chars().next()?.len_utf8();
+
+            let span = Span { from: 0, end: len };
+
+            Some(Ok(Token::new("broken", &source[..len], span)))
+        }
+
+        let err = tokenize(
+            &SrcFileInfo::from_str("abc".to_owned()),
+            &[broken_m as FnMatcher],
+        )
+        .unwrap_err();
+
+        assert_eq!(err.start, 1);
+        assert!(matches!(
+            err.reason,
+            TokenizeErrorReason::ZeroAdvance(name) if name == str2sym("broken")
+        ));
+    }
+
+    #[test]
+    fn test_tokenize_traced() {
+        use crate::{tokenize, tokenize_traced};
+
+        let srcfile = js_srcfile();
+        let matchers = &js_rules::MATCHERS[..];
+        let (tokens, trace) = tokenize_traced(&srcfile, matchers).unwrap();
+
+        assert_eq!(tokens.len(), trace.len());
+        assert_eq!(
+            tokens.iter().map(|tok| tok.span).collect::<Vec<_>>(),
+            tokenize(&srcfile, matchers)
+                .unwrap()
+                .iter()
+                .map(|tok| tok.span)
+                .collect::<Vec<_>>()
+        );
+
+        let source = srcfile.get_srcstr();
+
+        for (tok, i) in tokens.iter().zip(trace) {
+            // the traced matcher reproduces the token, no earlier one matches
+            let from = tok.span.from;
+            let retok = matchers[i](&source[from..], from).unwrap().unwrap();
+
+            assert_eq!(retok.span, tok.span);
+            assert_eq!(retok.name, tok.name);
+            assert!(matchers[..i]
+                .iter()
+                .all(|m| m(&source[from..], from).is_none()));
+        }
+
+        // renamed through `@path` rule still maps back to its rule
+        let (tokens, trace) = tokenize_traced(
+            &SrcFileInfo::from_str(r#"x = "s""#.to_owned()),
+            matchers,
+        )
+        .unwrap();
+        let i = tokens.iter().position(|tok| tok.check_name("dqstr")).unwrap();
+        assert_eq!(js_rules::TOKEN_NAMES[trace[i]], "dqstr");
+    }
+
+    #[test]
+    fn test_span_shift() {
+        use crate::tokenize;
+
+        let parent = SrcFileInfo::from_str("let a = 1;\nlet b = `${x + yy}`;".to_owned());
+        let base = parent.get_srcstr().find("x + yy").unwrap();
+        let frag = SrcFileInfo::from_str("x + yy".to_owned()).with_base_offset(base);
+
+        let toks = tokenize(&frag, &js_rules::MATCHERS[..])
+            .unwrap()
+            .into_iter()
+            .map(|tok| tok.shift_span(frag.base_offset() as isize))
+            .collect::<Vec<_>>();
+
+        for tok in toks.iter() {
+            assert_eq!(
+                &parent.get_srcstr()[tok.span.from..tok.span.end],
+                tok.value_string()
+            );
+        }
+
+        let yy = toks.last().unwrap();
+        assert_eq!(
+            parent.span2srcspan(yy.span),
+            (SrcLoc::new((2, 16)), SrcLoc::new((2, 18)))
+        );
+
+        assert_eq!(
+            Span { from: 5, end: 7 }.shift(-5),
+            Span { from: 0, end: 2 }
+        );
+    }
+
+    #[test]
+    fn test_span_shift_underflow() {
+        assert_eq!(Span { from: 1, end: 2 }.checked_shift(-2), None);
+        assert_eq!(
+            Span { from: 1, end: 2 }.checked_shift(-1),
+            Some(Span { from: 0, end: 1 })
+        );
+        assert!(Token::eof().checked_shift_span(-1).is_none());
+    }
+
+    #[test]
+    fn test_source_map() {
+        use crate::SourceMap;
+
+        let mut srcmap = SourceMap::new();
+
+        let a = SrcFileInfo::from_str("ab\ncd\n".to_owned());
+        let b = SrcFileInfo::from_str("x\nyz".to_owned());
+
+        assert_eq!(srcmap.add(a.clone()), 0);
+        assert_eq!(srcmap.add(b.clone()), 6);
+        assert_eq!(srcmap.len(), 10);
+
+        assert_eq!(srcmap.lookup(0), Some((&a, SrcLoc::new((1, 1)))));
+        assert_eq!(srcmap.lookup(4), Some((&a, SrcLoc::new((2, 2)))));
+        assert_eq!(srcmap.lookup(6), Some((&b, SrcLoc::new((1, 1)))));
+        assert_eq!(srcmap.lookup(9), Some((&b, SrcLoc::new((2, 2)))));
+        assert_eq!(srcmap.lookup(10), Some((&b, SrcLoc::new((2, 3)))));
+        assert_eq!(srcmap.lookup(11), None);
+        assert_eq!(SourceMap::new().lookup(0), None);
+    }
+
+    #[test]
+    fn test_srcfile_len() {
+        let ascii = SrcFileInfo::from_str("let a = 1;\n".to_owned());
+        assert_eq!((ascii.byte_len(), ascii.char_len()), (11, 11));
+
+        let multibyte = SrcFileInfo::from_str("let 变量 = \"é\";".to_owned());
+        assert_eq!((multibyte.byte_len(), multibyte.char_len()), (18, 13));
+
+        let srcfile = js_srcfile();
+        assert_eq!(srcfile.char_len(), srcfile.get_srcstr().chars().count());
+    }
+
+    #[test]
+    fn test_token_char_span() {
+        use crate::{tokenize, tokenize_multi, MultiTokenMatcher};
+
+        let srcfile = SrcFileInfo::from_str("x = \"变量é\" + b".to_owned());
+        let check = |toks: &[Token], source: &str| {
+            let mut char_from = 0;
+
+            for tok in toks {
+                let char_len = tok.span.chars_count(source);
+
+                assert_eq!(tok.char_from, Some(char_from));
+                assert_eq!(tok.char_len, Some(char_len));
+                assert_eq!(tok.span_chars_count(""), char_len);
+
+                char_from += char_len;
+            }
+        };
+
+        check(
+            &tokenize(&srcfile, &js_rules::MATCHERS[..]).unwrap(),
+            srcfile.get_srcstr(),
+        );
+        check(&tokenize2_ident("ab  cd\ne").unwrap(), "ab  cd\ne");
+
+        fn any_m(source: &str, from: usize) -> Option<crate::MultiTokenMatchResult> {
+            let c = source.chars().next()?;
+
+            Some(Ok(vec![Token::new(
+                "eof",
+                "",
+                Span { from, end: from + c.len_utf8() },
+            )]))
+        }
+        check(
+            &tokenize_multi(&srcfile, &[any_m as MultiTokenMatcher]).unwrap(),
+            srcfile.get_srcstr(),
+        );
+
+        // constructed outside tokenization, scan the source
+        let tok = Token::new("eof", "", Span { from: 5, end: 11 });
+        assert_eq!(tok.span_chars_count(srcfile.get_srcstr()), 2);
+    }
+
+    #[test]
+    fn test_srcfile_slice() {
+        let srcfile = SrcFileInfo::from_str("ab\n变量\ncd".to_owned());
+
+        assert_eq!(srcfile.slice(Span { from: 0, end: 2 }), Some("ab"));
+        assert_eq!(srcfile.slice(Span { from: 3, end: 9 }), Some("变量"));
+        assert_eq!(srcfile.slice(Span { from: 12, end: 12 }), Some(""));
+
+        // out of range, inverted, inside a multibyte char
+        assert_eq!(srcfile.slice(Span { from: 10, end: 13 }), None);
+        assert_eq!(srcfile.slice(Span { from: 2, end: 1 }), None);
+        assert_eq!(srcfile.slice(Span { from: 4, end: 9 }), None);
+
+        assert_eq!(srcfile.slice_lines(1, 1), Some("ab\n"));
+        assert_eq!(srcfile.slice_lines(2, 3), Some("变量\ncd"));
+        assert_eq!(srcfile.slice_lines(1, 3), Some(srcfile.get_srcstr()));
+        assert_eq!(srcfile.slice_lines(0, 1), None);
+        assert_eq!(srcfile.slice_lines(2, 1), None);
+        assert_eq!(srcfile.slice_lines(3, 4), None);
+
+        assert_eq!(srcfile.linestr(6), Some("变量\n"));
+    }
+
+    #[test]
+    fn test_with_fresh_interner() {
+        use crate::{interner_len, str2sym, sym2str, with_fresh_interner};
+
+        let outer = str2sym("outer");
+        Token::eof().is_trivia(); // intern the default trivia names
+        js_rules::id_m("abc", 0); // and the matcher name
+        let outer_len = interner_len();
+
+        let inner_len = with_fresh_interner(|| {
+            for i in 0..100 {
+                str2sym(&format!("tmp{i}"));
+            }
+
+            // trivia names still work inside the scope
+            assert!(Token::eof().rename("sp").is_trivia());
+
+            // so do the token names cached by the matchers
+            let tok = js_rules::id_m("abc", 0).unwrap().unwrap();
+            assert!(tok.check_name("id"));
+
+            interner_len()
+        });
+
+        assert!(inner_len >= 100);
+        assert_eq!(interner_len(), outer_len);
+        assert_eq!(sym2str(outer), "outer");
+        assert!(Token::eof().rename("newline").is_trivia());
+
+        let tok = js_rules::id_m("abc", 0).unwrap().unwrap();
+        assert!(tok.check_name("id"));
+    }
+
+    #[test]
+    fn test_str2syms() {
+        use crate::{str2sym, str2syms, sym2str};
+
+        let keywords = ["let", "fn", "if", "let"];
+        let syms = str2syms(&keywords);
+
+        assert_eq!(
+            syms.iter().map(|sym| sym2str(*sym)).collect::<Vec<_>>(),
+            keywords
+        );
+        assert_eq!(syms[0], syms[3]);
+        assert_eq!(syms[1], str2sym("fn"));
+        assert!(str2syms(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_seed_interner() {
+        use string_interner::Symbol as _;
+
+        use crate::{prelude::seed_interner, with_fresh_interner};
+
+        const KEYWORDS: &[&str] = &["let", "fn", "return"];
+        const FN_IDX: usize = 1;
+
+        with_fresh_interner(|| {
+            let syms = seed_interner(KEYWORDS);
+
+            assert_eq!(
+                syms.iter().map(|sym| sym.0.to_usize()).collect::<Vec<_>>(),
+                [0, 1, 2]
+            );
+
+            let tok = Token::eof().rename("fn");
+            assert!(tok.name == syms[FN_IDX]);
+            assert!(tok.name != syms[0]);
+        });
+    }
+
+    #[test]
+    fn test_token_list_fmt() {
+        use crate::{prelude::tokenize_str, TokenList};
+
+        let tokens = tokenize_str("a + \"b\"", &js_rules::MATCHERS[..]).unwrap();
+
+        assert_eq!(format!("{}", TokenList(&tokens)), "a\n \n+\n \nb\n");
+        assert_eq!(
+            format!("{:?}", TokenList(&tokens[..3])),
+            "<id> \"a\" 0..1\n<sp> \" \" 1..2\n<add> \"+\" 2..3\n"
+        );
+        assert_eq!(format!("{}", TokenList(&[])), "");
+    }
+
+    #[test]
+    fn test_token_lens() {
+        use crate::prelude::dqstr_m;
+
+        let tok = dqstr_m(r#""é变""#, 0).unwrap().unwrap();
+
+        assert!(tok.check_value("é变"));
+        assert_eq!(tok.span_len(), 7);
+        assert_eq!(tok.value_bytes_len(), 5);
+        assert_eq!(tok.chars_len(), 2);
+    }
+
+    #[test]
+    fn test_token_cursor() {
+        use crate::{prelude::tokenize_str, TokenCursor};
+
+        let tokens = tokenize_str("let a = 1;", &js_rules::MATCHERS[..])
+            .unwrap()
+            .into_iter()
+            .filter(|tok| !tok.is_trivia())
+            .collect::<Vec<_>>();
+        let mut cursor = TokenCursor::new(&tokens);
+
+        assert!(cursor.peek().check_value("let"));
+        assert!(cursor.peek_nth(2).check_name("assign"));
+        assert!(cursor.next().check_value("let"));
+
+        let id = cursor.expect_name("id").unwrap();
+        assert!(id.check_value("a"));
+
+        let unexpected = cursor.expect_name("semi").unwrap_err();
+        assert!(unexpected.check_name("assign"));
+        assert_eq!(cursor.pos(), 2);
+
+        assert!(cursor.eat_name("assign"));
+        assert!(!cursor.eat_name("assign"));
+        assert!(cursor.eat_name("lit_int"));
+        assert!(cursor.eat_name("semi"));
+
+        assert!(cursor.is_eof());
+        assert!(cursor.peek_nth(3).check_name("eof"));
+        assert!(cursor.peek_nth(usize::MAX).check_name("eof"));
+        assert!(cursor.next().check_name("eof"));
+        assert!(cursor.expect_name("eof").is_ok());
+        assert_eq!(cursor.peek().span, Span { from: 10, end: 10 });
+    }
+
+    #[test]
+    fn test_rename_all() {
+        use std::collections::HashMap;
+
+        use crate::prelude::{rename_all, rename_all_by_name, tokenize_str};
+
+        let tokens = tokenize_str("f = (a) => a => 1", &js_rules::MATCHERS[..])
+            .unwrap();
+        let names = |toks: &[Token]| {
+            toks.iter()
+                .filter(|tok| !tok.is_trivia())
+                .map(|tok| tok.name_string())
+                .collect::<Vec<_>>()
+        };
+
+        let by_value = rename_all(
+            &tokens,
+            &HashMap::from([("=>", "fat_arrow"), ("=", "bind")]),
+        );
+        assert_eq!(
+            names(&by_value),
+            [
+                "id", "bind", "lparen", "id", "rparen", "fat_arrow", "id",
+                "fat_arrow", "lit_int"
+            ]
+        );
+
+        let by_name = rename_all_by_name(
+            &tokens,
+            &HashMap::from([("double_arrow", "fat_arrow")]),
+        );
+        assert_eq!(
+            names(&by_name),
+            [
+                "id", "assign", "lparen", "id", "rparen", "fat_arrow", "id",
+                "fat_arrow", "lit_int"
+            ]
+        );
+        assert_eq!(by_name[10].span, tokens[10].span);
+    }
+
+    #[test]
+    fn test_srcfile_filename_dirname() {
+        use std::path::PathBuf;
+
+        let with_path = |path: &str| {
+            let mut srcfile = SrcFileInfo::from_str(String::new());
+            srcfile.path = PathBuf::from(path);
+
+            (srcfile.dirname(), srcfile.filename())
+        };
+        let pair = |dir: &str, file: &str| (dir.to_owned(), file.to_owned());
+
+        let anonymous = SrcFileInfo::from_str("a".to_owned());
+        assert_eq!((anonymous.dirname(), anonymous.filename()), pair("", ""));
+
+        assert_eq!(with_path("/"), pair("/", ""));
+        assert_eq!(with_path("a.js"), pair("", "a.js"));
+        assert_eq!(with_path("/a.js"), pair("/", "a.js"));
+        assert_eq!(with_path("src/lib/"), pair("src", "lib"));
+        assert_eq!(with_path("/src/a.js"), pair("/src", "a.js"));
+        assert_eq!(with_path(r"C:\src\a.js"), pair(r"C:\src", "a.js"));
+    }
+
+    #[test]
+    fn test_error_display_anonymous() {
+        use crate::prelude::tokenize_str;
+
+        let err = tokenize_str("a = 1;\nb = é @ 2", &js_rules::MATCHERS[..])
+            .unwrap_err();
+        let msg = err.to_string();
+
+        assert!(msg.contains("--> <anonymous>:2:5"), "{msg}");
+        assert!(!msg.contains("--> :"), "{msg}");
+        assert!(msg.contains("\nb = é @ 2\n    ^----\n"), "{msg}");
+    }
+
+    #[test]
+    fn test_tokenize_into() {
+        use crate::{tokenize, tokenize_into, TokenList};
+
+        let mut tokens = vec![];
+        let a = SrcFileInfo::from_str("a + b".to_owned());
+        let b = SrcFileInfo::from_str("c;".to_owned());
+
+        tokenize_into(&a, &js_rules::MATCHERS[..], &mut tokens).unwrap();
+        assert_eq!(tokens.len(), 5);
+
+        tokenize_into(&b, &js_rules::MATCHERS[..], &mut tokens).unwrap();
+        assert_eq!(
+            TokenList(&tokens[5..]).to_string(),
+            TokenList(&tokenize(&b, &js_rules::MATCHERS[..]).unwrap()).to_string()
+        );
+
+        // reuse the allocation
+        let cap = tokens.capacity();
+        tokens.clear();
+        tokenize_into(&a, &js_rules::MATCHERS[..], &mut tokens).unwrap();
+        assert_eq!(tokens.capacity(), cap);
+
+        let err = tokenize_into(
+            &SrcFileInfo::from_str("a @".to_owned()),
+            &js_rules::MATCHERS[..],
+            &mut tokens,
+        )
+        .unwrap_err();
+        assert_eq!(err.start, 2);
+        assert_eq!(tokens.len(), 7);
+    }
+
+    #[test]
+    fn test_tokenize_error_shared_src() {
+        use std::sync::Arc;
+
+        use crate::{prelude::tokenize_str, ErrorSrc};
+
+        let srcfile = SrcFileInfo::from_str("a @ b".to_owned());
+        let errsrc = ErrorSrc::new(&srcfile);
+
+        let errors = (0..100)
+            .map(|i| errsrc.error(TokenizeErrorReason::UnrecognizedToken, i % 5))
+            .collect::<Vec<_>>();
+
+        // one copy of the source for all the errors (and `errsrc`)
+        assert_eq!(Arc::strong_count(errors[0].src()), 101);
+        assert!(errors.iter().all(|err| Arc::ptr_eq(err.src(), errors[0].src())));
+        assert_eq!(**errors[99].src(), srcfile);
+
+        let err = tokenize_str("a @ b", &js_rules::MATCHERS[..]).unwrap_err();
+        assert_eq!(Arc::strong_count(err.src()), 1);
+        assert_eq!(Arc::strong_count(err.clone().src()), 2);
+    }
+
+    #[test]
+    fn test_map_value() {
+        use crate::prelude::map_value;
+
+        let id_m = map_value(js_rules::id_m, |s| s.to_lowercase());
+
+        let tok = id_m("FOO + 1", 3).unwrap().unwrap();
+        assert!(tok.check_name("id"));
+        assert!(tok.check_value("foo"));
+        assert_eq!(tok.span, Span { from: 3, end: 6 });
+
+        assert!(id_m("+ 1", 0).is_none());
+    }
+
+    #[test]
+    fn test_char_lit_m() {
+        use crate::prelude::char_lit_m;
+
+        for (src, val) in [
+            ("'a' ", "a"),
+            ("'\\n'", "\\n"),
+            ("'\\t'", "\\t"),
+            ("'\\\\'", "\\\\"),
+            ("'\\''", "\\'"),
+            ("'\\x7f'", "\\x7f"),
+            ("'\\u{1F600}'", "\\u{1F600}"),
+            ("'中'", "中"),
+        ] {
+            let tok = char_lit_m(src, 0).unwrap().unwrap();
+            assert!(tok.check_name("char_lit"));
+            assert!(tok.check_value(val), "{src}");
+            assert_eq!(tok.span_len(), val.len() + 2);
+        }
+
+        assert!(matches!(
+            char_lit_m("'ab'", 0),
+            Some(Err(TokenizeErrorReason::UnexpectedPostfix))
+        ));
+        assert!(matches!(
+            char_lit_m("'\\q'", 0),
+            Some(Err(TokenizeErrorReason::UnrecognizedEscaped('q')))
+        ));
+        assert!(matches!(
+            char_lit_m("'\\xZZ'", 0),
+            Some(Err(TokenizeErrorReason::UnrecognizedEscaped('x')))
+        ));
+        assert!(matches!(
+            char_lit_m("'\\u{D800}'", 0),
+            Some(Err(TokenizeErrorReason::UnrecognizedEscaped('u')))
+        ));
+        assert!(char_lit_m("a'", 0).is_none());
+    }
+
+    #[test]
+    fn test_regex_or_div() {
+        use crate::prelude::regex_or_div;
+
+        // x = /ab/
+        let assign = js_rules::assign_m("= /ab/", 2).unwrap().unwrap();
+        let tok = regex_or_div(Some(&assign), "/ab/", 4).unwrap().unwrap();
+        assert!(tok.check_name("lit_regex"));
+        assert!(tok.check_value("/ab/"));
+        assert_eq!(tok.span, Span { from: 4, end: 8 });
+
+        let tok = regex_or_div(None, "/ab/", 0).unwrap().unwrap();
+        assert!(tok.check_name("lit_regex"));
+
+        // a / b / c
+        let a = js_rules::id_m("a / b / c", 0).unwrap().unwrap();
+        let tok = regex_or_div(Some(&a), "/ b / c", 2).unwrap().unwrap();
+        assert!(tok.check_name("div"));
+        assert!(tok.check_value("/"));
+        assert_eq!(tok.span, Span { from: 2, end: 3 });
+
+        let rparen = js_rules::rparen_m(")", 0).unwrap().unwrap();
+        let tok = regex_or_div(Some(&rparen), "/ 2", 1).unwrap().unwrap();
+        assert!(tok.check_name("div"));
+
+        assert!(regex_or_div(Some(&a), "b", 2).is_none());
+    }
+
+    mod comment_rules {
+        use crate::{self as m6lexerkit, TokenMatchResult};
+        use proc_macros::make_token_matcher_rules;
+
+        make_token_matcher_rules! {
+            sharp_line_comment => @m6lexerkit::prelude::line_comment_m("#"),
+            slash_line_comment => @m6lexerkit::prelude::line_comment_m("//"),
+            slash_block_comment => @m6lexerkit::prelude::block_comment_m("/*", "*/"),
+            id => "[[:alpha:]_][[:alnum:]_]*",
+            sp => "[[:space:]]+"
+        }
+    }
+
+    #[test]
+    fn test_comment_m() {
+        use crate::tokenize;
+
+        let srcfile = SrcFileInfo::from_str(
+            "# comment\n// comment\n/* line1\n line2 */ a".to_owned(),
+        );
+        let tokens = tokenize(&srcfile, &comment_rules::MATCHERS[..]).unwrap();
+
+        assert_eq!(tokens.len(), 7);
+        assert!(tokens[0].check_name("sharp_line_comment"));
+        assert!(tokens[0].check_value("# comment"));
+        assert!(tokens[2].check_name("slash_line_comment"));
+        assert!(tokens[2].check_value("// comment"));
+        assert!(tokens[4].check_name("slash_block_comment"));
+        assert!(tokens[4].check_value("/* line1\n line2 */"));
+        assert!(tokens[6].check_name("id"));
+
+        let unterminated = SrcFileInfo::from_str("/* a\n b".to_owned());
+        let err = tokenize(&unterminated, &comment_rules::MATCHERS[..])
+            .unwrap_err();
+        assert!(matches!(err.reason, TokenizeErrorReason::Unterminated));
+    }
+
+    #[test]
+    fn test_shebang_m() {
+        use crate::{
+            prelude::{shebang_m, shebang_tok_m},
+            tokenize,
+        };
+
+        let mut matchers = vec![shebang_m as m6lexerkit::FnMatcher];
+        matchers.extend(js_rules::MATCHERS.iter());
+
+        let srcfile = SrcFileInfo::from_str("#!/bin/bash\nx = 1".to_owned());
+        let tokens = tokenize(&srcfile, &matchers[..]).unwrap();
+
+        assert_eq!(tokens.len(), 6);
+        assert!(tokens[0].check_name("newline"));
+        assert_eq!(tokens[0].span, Span { from: 11, end: 12 });
+        assert_eq!(tokens[0].char_from, Some(11));
+        assert!(tokens[1].check_name("id") && tokens[1].check_value("x"));
+        assert_eq!(tokens[1].span, Span { from: 12, end: 13 });
+
+        // only at the start of source
+        assert!(shebang_m("#!/bin/bash", 3).is_none());
+
+        let tok = shebang_tok_m("#!/bin/bash\n", 0).unwrap().unwrap();
+        assert!(tok.check_name("shebang") && tok.check_value("#!/bin/bash"));
+        assert!(!tok.is_skip());
+    }
+
+    #[test]
+    fn test_error_display_multiline() {
+        use crate::tokenize;
+
+        let srcfile =
+            SrcFileInfo::from_str("x = \"abc\ndef\nghi".to_owned());
+        let err = tokenize(&srcfile, &js_rules::MATCHERS[..]).unwrap_err();
+
+        assert!(matches!(err.reason, TokenizeErrorReason::Unterminated));
+        assert_eq!(err.span_end(), Some(16));
+
+        let rendered = err.to_string();
+        assert!(rendered.contains(
+            "x = \"abc\n    ^---\ndef\n^--\nghi\n^--\n--> <anonymous>:1:5"
+        ), "{rendered}");
+    }
+
+    #[test]
+    fn test_error_render_ansi() {
+        let err = tokenize2_ident("a @").unwrap_err();
+
+        let plain = err.to_string();
+        assert!(!plain.contains('\x1b'));
+
+        let mut colored = String::new();
+        err.render(&mut colored, true).unwrap();
+        assert!(colored.contains("\x1b[31m^\x1b[0m"));
+        assert!(colored.contains("\x1b[34m--> <anonymous>:1:3\x1b[0m"));
+        assert_eq!(colored.replace("\x1b[31m", "").replace("\x1b[34m", "")
+            .replace("\x1b[0m", ""), plain);
+
+        // stderr of the test harness isn't a TTY
+        #[cfg(not(feature = "ansi"))]
+        assert_eq!(err.render_ansi(), plain);
+    }
+
+    #[test]
+    fn test_tokenize_iter() {
+        use crate::tokenize_iter;
+
+        let srcfile = SrcFileInfo::from_str("a @ b".to_owned());
+        let stream = tokenize_iter(&srcfile, &js_rules::MATCHERS[..])
+            .collect::<Vec<_>>();
+
+        assert_eq!(stream.len(), 5);
+        assert!(stream[0].as_ref().unwrap().check_value("a"));
+        assert!(stream[1].as_ref().unwrap().check_name("sp"));
+
+        let err = stream[2].as_ref().unwrap_err();
+        assert!(matches!(err.reason, TokenizeErrorReason::UnrecognizedToken));
+        assert_eq!(err.start, 2);
+
+        assert!(stream[3].as_ref().unwrap().check_name("sp"));
+        let b = stream[4].as_ref().unwrap();
+        assert!(b.check_value("b"));
+        assert_eq!(b.span, Span { from: 4, end: 5 });
+        assert_eq!(b.char_from, Some(4));
+    }
+
+    #[test]
+    fn test_span_relations() {
+        let a = Span { from: 0, end: 3 };
+        let b = Span { from: 2, end: 5 };
+        let c = Span { from: 5, end: 6 };
+        let d = Span { from: 8, end: 9 };
+
+        // overlapping
+        assert!(a.overlaps(&b) && b.overlaps(&a));
+        assert!(!a.adjacent(&b));
+        assert_eq!(a.gap(&b), None);
+
+        // adjacent
+        assert!(!b.overlaps(&c));
+        assert!(b.adjacent(&c) && c.adjacent(&b));
+        assert_eq!(b.gap(&c), None);
+
+        // gapped
+        assert!(!c.overlaps(&d) && !c.adjacent(&d));
+        assert_eq!(c.gap(&d), Some(Span { from: 6, end: 8 }));
+        assert_eq!(d.gap(&c), Some(Span { from: 6, end: 8 }));
+    }
+
+    #[test]
+    fn test_srcfile_from_reader() {
+        use std::io::Cursor;
+
+        let srcfile = SrcFileInfo::from_reader(
+            "<stdin>",
+            Cursor::new("a b\nc".as_bytes()),
+        )
+        .unwrap();
+
+        assert_eq!(srcfile.get_srcstr(), "a b\nc");
+        assert_eq!(srcfile.get_path().to_str(), Some("<stdin>"));
+        assert_eq!(srcfile.linestr(4), Some("c"));
+
+        let err = crate::tokenize(&srcfile, &path_rules::MATCHERS[..])
+            .unwrap_err();
+        assert!(err.to_string().contains("--> <stdin>:1:4"), "{err}");
+
+        // not UTF-8
+        assert!(
+            SrcFileInfo::from_reader("<stdin>", Cursor::new(&[0xff_u8][..]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_tokenize_range() {
+        use crate::{tokenize, tokenize_range};
+
+        let srcfile = SrcFileInfo::from_str(
+            "let a = 1;\nlet bb = \"中\" + a;\nlet c = 3;\n".to_owned(),
+        );
+        let full = tokenize(&srcfile, &js_rules::MATCHERS[..]).unwrap();
+
+        // `bb = "中"`
+        let range = 15..25;
+        let part =
+            tokenize_range(&srcfile, &js_rules::MATCHERS[..], range.clone())
+                .unwrap();
+        let expected = full
+            .iter()
+            .filter(|tok| tok.span.from < range.end && tok.span.end > range.start)
+            .collect::<Vec<_>>();
+
+        assert!(part[0].check_value("bb"));
+        assert!(part.last().unwrap().check_name("dqstr"));
+        assert_eq!(part.len(), expected.len());
+
+        for (tok, exp) in part.iter().zip(expected) {
+            assert_eq!(tok.span, exp.span);
+            assert_eq!(tok.char_from, exp.char_from);
+            assert!(tok.name == exp.name && tok.value == exp.value);
+        }
+    }
+
+    #[test]
+    fn test_tokenize_recover_sync() {
+        use crate::tokenize_recover_sync;
+
+        let srcfile = SrcFileInfo::from_str("a = @ # b; c;".to_owned());
+        let (tokens, errors) = tokenize_recover_sync(
+            &srcfile,
+            &js_rules::MATCHERS[..],
+            &["semi"],
+        );
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].start, 4);
+        // `@ # b` skipped
+        assert_eq!(errors[0].span_end(), Some(9));
+
+        let names = tokens
+            .iter()
+            .map(|tok| tok.name_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["id", "sp", "assign", "sp", "semi", "sp", "id", "semi"]
+        );
+        assert_eq!(tokens[4].span, Span { from: 9, end: 10 });
+        assert_eq!(tokens[4].char_from, Some(9));
+    }
+
+    #[test]
+    fn test_token_histogram() {
+        use crate::prelude::{token_histogram, tokenize_str};
+
+        let tokens =
+            tokenize_str("let a = b + c;", &js_rules::MATCHERS[..]).unwrap();
+        let hist = token_histogram(&tokens);
+
+        assert_eq!(hist["id"], 4);
+        assert_eq!(hist["sp"], 5);
+        assert_eq!(hist.get("lit_int"), None);
+        assert_eq!(
+            hist.keys().collect::<Vec<_>>(),
+            ["add", "assign", "id", "semi", "sp"]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_bad_span_end() {
+        use crate::{str2sym, tokenize, FnMatcher, TokenMatchResult};
+
+        // the old trailing context hack of `add`: count the char after `+`
+        // into the span, even at EOF
+        fn add_hack_m(source: &str, from: usize) -> Option<TokenMatchResult> {
+            if !source.starts_with('+') {
+                return None;
+            }
+
+            Some(Ok(Token::new("add", "+", Span { from, end: from + 2 })))
+        }
+
+        let matchers = [add_hack_m as FnMatcher, js_rules::id_m];
+
+        // past EOF
+        let err = tokenize(&SrcFileInfo::from_str("a+".to_owned()), &matchers)
+            .unwrap_err();
+        assert_eq!(err.start, 1);
+        assert!(matches!(
+            err.reason,
+            TokenizeErrorReason::BadSpanEnd(name) if name == str2sym("add")
+        ));
+
+        // inside the multi-bytes char following
+        let err = tokenize(&SrcFileInfo::from_str("a+é".to_owned()), &matchers)
+            .unwrap_err();
+        assert!(matches!(err.reason, TokenizeErrorReason::BadSpanEnd(_)));
+    }
+
+    #[test]
+    fn test_simple_char_matcher() {
+        let e_acute = SimpleCharMatcher::new("é");
+
+        assert!(e_acute.is_match('é'));
+        assert!(!e_acute.is_match('e'));
+
+        let zhong = SimpleCharMatcher::from_char('中');
+
+        assert!(zhong.is_match('中'));
+        assert!(!zhong.is_match('国'));
+    }
+
+    #[test]
+    #[should_panic(expected = "empty target")]
+    fn test_simple_char_matcher_empty() {
+        SimpleCharMatcher::new("");
+    }
+
+    #[test]
+    fn test_simple_char_matcher_try_new() {
+        use crate::EmptyTargetError;
+
+        assert!(SimpleCharMatcher::try_new("ab").unwrap().is_match('a'));
+        assert_eq!(
+            SimpleCharMatcher::try_new("").err(),
+            Some(EmptyTargetError)
+        );
+    }
+
+    #[test]
+    fn test_float_m() {
+        use crate::prelude::{
+            float_m, FloatForms, FLAG_HEX, FLAG_OUT_OF_RANGE,
+        };
+
+        let float_m = float_m(FloatForms::default());
+
+        let tok = float_m("Infinity;", 0).unwrap().unwrap();
+        assert!(tok.check_name("float") && tok.check_value("Infinity"));
+        assert!(!tok.has_flag(FLAG_OUT_OF_RANGE));
+
+        let tok = float_m("1e400", 0).unwrap().unwrap();
+        assert!(tok.check_value("1e400"));
+        assert!(tok.has_flag(FLAG_OUT_OF_RANGE));
+
+        let tok = float_m("1.5E308", 0).unwrap().unwrap();
+        assert!(tok.check_value("1.5e308"));
+        assert!(!tok.has_flag(FLAG_OUT_OF_RANGE));
+
+        let tok = float_m("0x1.8p3 ", 2).unwrap().unwrap();
+        assert!(tok.check_value("12.0") && tok.has_flag(FLAG_HEX));
+        assert_eq!(tok.span, Span { from: 2, end: 9 });
+
+        // left to the integer rules
+        assert!(float_m("42", 0).is_none());
+        assert!(float_m("Infinityx", 0).is_none());
+
+        let plain = crate::prelude::float_m(FloatForms {
+            special: false,
+            hex: false,
+        });
+        assert!(plain("NaN", 0).is_none());
+        assert!(plain("0x1p3", 0).is_none());
+    }
+
+    mod alt_rules {
+        use crate::{
+            self as m6lexerkit,
+            prelude::{aqstr_m, dqstr_m, sqstr_m},
+            TokenMatchResult,
+        };
+        use proc_macros::make_token_matcher_rules;
+
+        make_token_matcher_rules! {
+            string => @m6lexerkit::prelude::alt(
+                "string", &[dqstr_m, sqstr_m, aqstr_m]
+            ),
+            sp => "[[:blank:]]+"
+        }
+    }
+
+    #[test]
+    fn test_alt() {
+        use crate::tokenize;
+
+        let srcfile = SrcFileInfo::from_str(r#""a" 'b' `c`"#.to_owned());
+        let tokens = tokenize(&srcfile, &alt_rules::MATCHERS[..]).unwrap();
+        let strings = tokens
+            .iter()
+            .filter(|tok| tok.check_name("string"))
+            .map(|tok| tok.value_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(tokens.len(), 5);
+        assert_eq!(strings, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_separator_between() {
+        use crate::prelude::{
+            separator_between, tokenize_str, trim, trim_names,
+        };
+
+        let source = "a \t  b\tc // d";
+        let tokens = trim_names(
+            tokenize_str(source, &js_rules::MATCHERS[..]),
+            &["sp"],
+        )
+        .unwrap();
+
+        let sep = |a: usize, b: usize| {
+            separator_between(source, &tokens[a], &tokens[b])
+        };
+
+        assert_eq!(sep(0, 1), Some(" \t  "));
+        assert_eq!(sep(1, 2), Some("\t"));
+        assert_eq!(sep(2, 1), None);
+        assert_eq!(separator_between("a", &tokens[1], &tokens[2]), None);
+        assert!(tokens[3].check_name("slash_line_comment"));
+
+        // the comment is trivia too
+        let tokens =
+            trim(tokenize_str(source, &js_rules::MATCHERS[..])).unwrap();
+        assert_eq!(tokens.len(), 3);
+    }
+
+    #[test]
+    fn test_ident_m_unicode() {
+        use crate::prelude::{ident_m_unicode, is_confusable, FLAG_CONFUSABLE};
+
+        let tok = ident_m_unicode("paypal = 1", 0).unwrap().unwrap();
+        assert!(tok.check_name("id") && tok.check_value("paypal"));
+        assert!(!tok.has_flag(FLAG_CONFUSABLE));
+
+        // Cyrillic `а` (U+0430)
+        let tok = ident_m_unicode("p\u{430}ypal", 0).unwrap().unwrap();
+        assert_eq!(tok.span_len(), 7);
+        assert!(tok.has_flag(FLAG_CONFUSABLE));
+
+        assert!(!is_confusable("变量_x1"));
+        assert!(!is_confusable("привет"));
+        assert!(ident_m_unicode("1x", 0).is_none());
+    }
+
+    #[test]
+    fn test_token_inner_span() {
+        use crate::prelude::tokenize_str;
+
+        let source = r#"x = "héllo";"#;
+        let tokens = tokenize_str(source, &js_rules::MATCHERS[..]).unwrap();
+        let tok = tokens.iter().find(|tok| tok.check_name("dqstr")).unwrap();
+        let inner = tok.inner_span(source, 1, 1).unwrap();
+
+        assert_eq!(tok.span, Span { from: 4, end: 12 });
+        assert_eq!(inner, Span { from: 5, end: 11 });
+        assert_eq!(&source[inner.from..inner.end], tok.value_string());
+
+        // overlapping delimiters, inside `é`
+        assert_eq!(tok.inner_span(source, 5, 4), None);
+        assert_eq!(tok.inner_span(source, 3, 1), None);
+        assert_eq!(tok.inner_span(source, 0, 20), None);
+    }
+
+    #[test]
+    fn test_token_kind() {
+        use crate::{prelude::tokenize_str, TokenClassifier, TokenKind};
+
+        let tokens =
+            tokenize_str("f(12) // x", &js_rules::MATCHERS[..]).unwrap();
+        let classifier = TokenClassifier::new();
+        let kinds = tokens
+            .iter()
+            .map(|tok| tok.kind(&classifier))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            kinds,
+            [
+                TokenKind::Identifier,
+                TokenKind::Punctuation,
+                TokenKind::Literal,
+                TokenKind::Punctuation,
+                TokenKind::Whitespace,
+                TokenKind::Comment,
+            ]
+        );
+
+        let classifier = TokenClassifier::new().with("id", TokenKind::Keyword);
+        assert_eq!(tokens[0].kind(&classifier), TokenKind::Keyword);
+        assert_eq!(classifier.classify("lit_int"), TokenKind::Literal);
+        assert_eq!(classifier.classify("lparen"), TokenKind::Punctuation);
+        assert_eq!(classifier.classify("heredoc"), TokenKind::Other);
+    }
+
+    #[test]
+    fn test_check_matcher_overlap() {
+        use crate::prelude::{check_matcher_overlap, OverlapWarning};
+
+        let warnings = check_matcher_overlap(&[
+            ("assign", "="),
+            ("eq", "=="),
+            ("id", "[[:alpha:]_][[:alnum:]_]*"),
+            ("lparen", r"\("),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            warnings,
+            [OverlapWarning {
+                first: "assign".to_owned(),
+                second: "eq".to_owned(),
+                sample: "==".to_owned(),
+            }]
+        );
+
+        // the longer first is fine
+        assert!(check_matcher_overlap(&[("eq", "=="), ("assign", "=")])
+            .unwrap()
+            .is_empty());
+
+        assert!(check_matcher_overlap(&[("eq", "=="), ("bad", "(")]).is_err());
+    }
+
+    crate::define_tokenizer! {
+        tiny,
+        trivia: [sp, slash_line_comment],
+        rules {
+            id      => "[[:alpha:]_][[:alnum:]_]*",
+            lit_int => "[0-9]+",
+            dqstr   => @m6lexerkit::prelude::dqstr_m,
+            slash_line_comment => @m6lexerkit::prelude::line_comment_m("//"),
+            assign  => "=",
+            sp      => "[[:space:]]+"
+        }
+    }
+
+    #[test]
+    fn test_define_tokenizer() {
+        let srcfile = SrcFileInfo::from_str(
+            "x = 1 // one\ny = \"s\"".to_owned(),
+        );
+        let tokens = tiny::tokenize(&srcfile).unwrap();
+        let names = tokens
+            .iter()
+            .map(|tok| tok.name_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, ["id", "assign", "lit_int", "id", "assign", "dqstr"]);
+        assert_eq!(tokens[5].span, Span { from: 17, end: 20 });
+        assert_eq!(tiny::TOKEN_NAMES.len(), 6);
+        assert_eq!(tiny::TRIVIA_NAMES, ["sp", "slash_line_comment"]);
+    }
+
+    mod transform_rules {
+        use crate::{self as m6lexerkit, TokenMatchResult};
+        use proc_macros::make_token_matcher_rules;
+
+        fn upper(s: &str) -> String {
+            s.to_uppercase()
+        }
+
+        fn strip_underscores(s: &str) -> String {
+            s.replace('_', "")
+        }
+
+        make_token_matcher_rules! {
+            kw      => "let|if" |> upper,
+            lit_int => "[0-9][0-9_]*" |> strip_underscores,
+            dqstr   => @m6lexerkit::prelude::dqstr_m |> upper,
+            sp      => "[[:blank:]]+"
+        }
+    }
+
+    #[test]
+    fn test_token_matcher_rules_transform() {
+        use crate::prelude::tokenize_str;
+
+        let tokens = tokenize_str(
+            r#"let 1_000 "s""#,
+            &transform_rules::MATCHERS[..],
+        )
+        .unwrap();
+
+        assert!(tokens[0].check_name("kw") && tokens[0].check_value("LET"));
+        assert!(tokens[2].check_value("1000"));
+        assert_eq!(tokens[2].span, Span { from: 4, end: 9 });
+        assert!(tokens[4].check_name("dqstr") && tokens[4].check_value("S"));
+    }
+
+    #[test]
+    fn test_boffset2visualcol() {
+        let srcfile = SrcFileInfo::from_str("\t\tx\n a\tb".to_owned());
+
+        // `x`
+        assert_eq!(srcfile.boffset2srcloc(2).col, 3);
+        assert_eq!(srcfile.boffset2visualcol(2), 9);
+        // `b`
+        assert_eq!(srcfile.boffset2srcloc(7).col, 4);
+        assert_eq!(srcfile.boffset2visualcol(7), 5);
+
+        let srcfile = srcfile.with_tab_width(8);
+        assert_eq!(srcfile.boffset2visualcol(2), 17);
+        assert_eq!(srcfile.boffset2srcloc(2).col, 3);
+
+        let srcfile = srcfile.with_tab_width(0);
+        assert_eq!(srcfile.tab_width(), 1);
+        assert_eq!(srcfile.boffset2visualcol(2), 3);
+
+        // past the end, inside a char
+        let srcfile = SrcFileInfo::from_str("\té".to_owned());
+        assert_eq!(srcfile.boffset2visualcol(100), 6);
+        assert_eq!(srcfile.boffset2visualcol(2), 5);
+
+        let err = crate::tokenize(
+            &SrcFileInfo::from_str("\tab @".to_owned()),
+            &js_rules::MATCHERS[..],
+        )
+        .unwrap_err();
+        // the caret under `@` with the tab expanded
+        assert!(err
+            .to_string()
+            .contains("    ab @\n       ^\n--> <anonymous>:1:5"));
+    }
+
+    #[test]
+    fn test_token_matcher_captures() {
+        use crate::TokenMatcher;
+
+        let matcher = TokenMatcher::new(
+            r"^((?P<major>\d+)\.(?P<minor>\d+)(?:\.(?P<patch>\d+))?)",
+            "version",
+        );
+
+        let (tok, named) = matcher.fetch_tok_captures("1.22.3 x", 5).unwrap();
+        assert!(tok.check_name("version") && tok.check_value("1.22.3"));
+        assert_eq!(tok.span, Span { from: 5, end: 11 });
+        assert_eq!(named["major"], "1");
+        assert_eq!(named["minor"], "22");
+        assert_eq!(named["patch"], "3");
+
+        // unmatched groups are left out
+        let (_, named) = matcher.fetch_tok_captures("1.2", 0).unwrap();
+        assert_eq!(named.len(), 2);
+        assert!(!named.contains_key("patch"));
+
+        assert!(matcher.fetch_tok_captures("x1.2", 0).is_none());
+    }
+
+    mod line_start_rules {
+        use crate::{self as m6lexerkit, TokenMatchResult};
+        use proc_macros::make_token_matcher_rules;
+
+        make_token_matcher_rules! {
+            header  => @m6lexerkit::prelude::at_line_start(
+                m6lexerkit::prelude::sharp_line_comment_m
+            ),
+            sharp   => "#",
+            id      => "[[:alpha:]_][[:alnum:]_]*",
+            sp      => "[[:blank:]]+",
+            newline => r"\n"
+        }
+    }
+
+    #[test]
+    fn test_at_line_start() {
+        use crate::prelude::tokenize_str;
+
+        let tokens = tokenize_str(
+            "# title\nsee # not\n# sub",
+            &line_start_rules::MATCHERS[..],
+        )
+        .unwrap();
+        let names = tokens
+            .iter()
+            .map(|tok| tok.name_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            names,
+            [
+                "header", "newline", "id", "sp", "sharp", "sp", "id",
+                "newline", "header"
+            ]
+        );
+        assert!(tokens[0].check_value("# title"));
+        assert_eq!(tokens[8].span, Span { from: 18, end: 23 });
+    }
+
+    #[test]
+    fn test_token_reintern() {
+        use crate::{prelude::tokenize_str, with_fresh_interner};
+
+        let outer_tok = tokenize_str("x", &js_rules::MATCHERS[..]).unwrap()[0];
+
+        let tokens = with_fresh_interner(|| {
+            let tokens = tokenize_str(
+                "fresh_only_name + 1",
+                &js_rules::MATCHERS[..],
+            )
+            .unwrap();
+
+            tokens.into_iter().map(Token::reintern).collect::<Vec<_>>()
+        });
+
+        assert!(tokens[0].check_name("id"));
+        assert!(tokens[0].check_value("fresh_only_name"));
+        assert_eq!(tokens[0].name, outer_tok.name);
+        assert!(tokens[4].check_name("lit_int") && tokens[4].check_value("1"));
+        assert_eq!(tokens[4].span, Span { from: 18, end: 19 });
+
+        // outside a scope it's a no-op
+        assert_eq!(outer_tok.reintern().value, outer_tok.value);
+    }
+
+    #[test]
+    fn test_srcfile_from_str_named() {
+        let srcfile =
+            SrcFileInfo::from_str_named("conf/app.toml", "a @".to_owned());
+
+        assert_eq!(srcfile.get_path().to_str(), Some("conf/app.toml"));
+        assert_eq!(srcfile.filename(), "app.toml");
+
+        let err = crate::tokenize(&srcfile, &js_rules::MATCHERS[..])
+            .unwrap_err();
+        assert!(err.to_string().contains("--> conf/app.toml:1:3"));
+    }
+
+    #[test]
+    fn test_srcfile_lines_with_ranges() {
+        let srcfile = SrcFileInfo::from_str("ab\n\nc中".to_owned());
+        let lines = srcfile.lines_with_ranges().collect::<Vec<_>>();
+
+        assert_eq!(
+            lines,
+            [
+                (1, Span { from: 0, end: 3 }, "ab\n"),
+                (2, Span { from: 3, end: 4 }, "\n"),
+                (3, Span { from: 4, end: 8 }, "c中"),
+            ]
+        );
+
+        let srcfile = SrcFileInfo::from_str("a\n".to_owned());
+        assert_eq!(srcfile.lines_with_ranges().count(), 1);
+        assert_eq!(SrcFileInfo::from_str(String::new()).lines_with_ranges().count(), 0);
+    }
+
+    #[test]
+    fn test_tokenize2_empty_and_blank_source() {
+        use super::tokenize;
+
+        for source in ["", "   "] {
+            let srcfile = SrcFileInfo::from_str(source.to_owned());
+            let toks1 = tokenize(&srcfile, &js_rules::MATCHERS[..]).unwrap();
+            let toks2 = tokenize2_ident(source).unwrap();
+
+            let shape = |toks: &[Token]| {
+                toks.iter()
+                    .map(|tok| (tok.name_string(), tok.span()))
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(shape(&toks1), shape(&toks2), "{source:?}");
+        }
+    }
+
+    #[test]
+    fn test_tokenize_bad_span_bounds() {
+        use crate::{str2sym, tokenize, FnMatcher, TokenMatchResult};
+
+        fn overlong_m(source: &str, from: usize) -> Option<TokenMatchResult> {
+            source.strip_prefix('#')?;
+
+            Some(Ok(Token::new("overlong", "#", Span { from, end: from + 100 })))
+        }
+
+        // span one byte ahead of the position
+        fn shifted_m(source: &str, from: usize) -> Option<TokenMatchResult> {
+            source.strip_prefix('@')?;
 
-            let value = str2sym(cap.get(4).unwrap().as_str());
-            let name = str2sym("__aux_tmp");
+            let span = Span { from: from + 1, end: from + 2 };
 
-            Some(Ok(Token { name, value, span }))
-        } else {
-            None
+            Some(Ok(Token::new("shifted", "@", span)))
         }
-    }
-
-    use crate as m6lexerkit;
 
-    make_token_matcher_rules! {
-        // Comment
-        sharp_line_comment  => r"#.*",
+        let matchers =
+            [overlong_m as FnMatcher, shifted_m, js_rules::id_m];
+
+        let err = tokenize(&SrcFileInfo::from_str("ab#".to_owned()), &matchers)
+            .unwrap_err();
+        assert_eq!(err.start, 2);
+        assert!(matches!(
+            err.reason,
+            TokenizeErrorReason::BadSpanEnd(name) if name == str2sym("overlong")
+        ));
+
+        let err = tokenize(&SrcFileInfo::from_str("a@b".to_owned()), &matchers)
+            .unwrap_err();
+        assert_eq!(err.start, 1);
+        assert!(matches!(
+            err.reason,
+            TokenizeErrorReason::MisplacedSpan(name) if name == str2sym("shifted")
+        ));
+    }
 
-        // White characters
-        sp      => "[[:blank:]]+",
-        newline => r#"\n\r?"#,
+    #[cfg(feature = "unicode-segmentation")]
+    #[test]
+    fn test_srcfile_boffset2graphemecol() {
+        // decomposed `é` (e + U+0301) takes two chars but one grapheme
+        let srcfile = SrcFileInfo::from_str("x\ncafe\u{301} = 1".to_owned());
+        let offset = srcfile.get_srcstr().find('=').unwrap();
+
+        assert_eq!(srcfile.boffset2srcloc(offset).col, 7);
+        assert_eq!(srcfile.boffset2graphemecol(offset), 6);
+        assert_eq!(srcfile.boffset2graphemecol(2), 1);
+    }
 
-        // Bracket
-        lparen => r"\(",
-        rparen => r"\)",
-        lbracket => r"\[",
-        rbracket => r"\]",
-        lbrace => r"\{",
-        rbrace => r"\}",
+    #[test]
+    fn test_token_map() {
+        let tok = Token::new("ident", "  ab  ", Span { from: 3, end: 9 });
 
-        // Delimiter
-        colon => ":",
-        question => r"\?",
-        rarrow => "->",
-        rdarrow  => "=>",
-        semi   => ";",
-        comma  => ",",
+        let tok = tok.map(|name, value| {
+            (name.to_uppercase(), value.trim().to_owned())
+        });
 
-        // Assign
-        assign => "=",
+        assert!(tok.check_name("IDENT"));
+        assert!(tok.check_value("ab"));
+        assert_eq!(tok.span(), Span { from: 3, end: 9 });
+    }
 
-        // Unary Operation
-        inc => r"\+\+",
-        dec => r"--",
-        not => "!",
+    #[test]
+    fn test_tokenize2_dfa_named() {
+        let dfamap = lexdfamap! {
+            ENTRY_ST => {
+                sp         | BLANK_ST,      false
+                ident_head | IDENT_NAME_ST, false
+                num        | NUM_ST,        false => lit_int
+            },
+            BLANK_ST => {
+                sp         | BLANK_ST,      false
+                ident_head | IDENT_NAME_ST, true
+                num        | NUM_ST,        true  => lit_int
+            },
+            IDENT_NAME_ST => {
+                ident      | IDENT_NAME_ST, false
+                sp         | BLANK_ST,      true
+            },
+            NUM_ST => {
+                num        | NUM_ST,        false
+                sp         | BLANK_ST,      true
+            }
+        };
+        // no regex for the numbers
+        let recognizer = token_recognizer![ 2 |
+            id => "[[:alpha:]_][[:alnum:]_]*",
+            sp => "[[:space:]]+"
+        |];
+
+        let srcfile = SrcFileInfo::from_str("ab 12 c 345".to_owned());
+        let tokens = tokenize2(&srcfile, &dfamap, &recognizer).unwrap();
+        let tokens = tokens
+            .iter()
+            .map(|tok| (tok.name_string(), tok.value_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            [
+                ("id", "ab"),
+                ("sp", " "),
+                ("lit_int", "12"),
+                ("sp", " "),
+                ("id", "c"),
+                ("sp", " "),
+                ("lit_int", "345"),
+            ]
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        );
+    }
 
-        // Binary Operation
-        sub    => "-",
-        add    => r"\+[^\+]",
-        mul    => r"\*",
-        div    => "/",
-        dot    => r"\.",
-        ge     => ">=",
-        le     => "<=",
-        lt     => "<",
-        gt     => ">",
-        neq    => "!=",
-        eq     => "==",
-        percent=> "%",
-        and    => "&&",
-        or     => r"\|\|"
+    #[test]
+    fn test_tokenize_debug() {
+        use crate::{tokenize, tokenize_debug};
+
+        let srcfile = SrcFileInfo::from_str("a+b".to_owned());
+        let matchers = &js_rules::MATCHERS[..];
+        let mut sink = vec![];
+
+        let tokens = tokenize_debug(&srcfile, matchers, &mut sink).unwrap();
+        let expected = tokenize(&srcfile, matchers).unwrap();
+        assert_eq!(tokens.len(), expected.len());
+        assert!(tokens.iter().zip(expected.iter()).all(|(lhs, rhs)| {
+            lhs.span() == rhs.span() && lhs.name == rhs.name
+        }));
+
+        let trace = String::from_utf8(sink).unwrap();
+        assert!(trace.starts_with("@0 (1:1)\n  #0 id \"a\""));
+        assert!(trace.contains("@1 (1:2)\n"));
+        assert!(trace.contains(" add \"+\" "));
+        assert!(trace.contains("-> @2\n"));
+        assert!(trace.ends_with("=> #0 id -> @3\n"));
+
+        // one answer per matcher tried, the error last
+        let srcfile = SrcFileInfo::from_str("@".to_owned());
+        let mut sink = vec![];
+
+        assert!(tokenize_debug(&srcfile, matchers, &mut sink).is_err());
+
+        let trace = String::from_utf8(sink).unwrap();
+        assert_eq!(trace.matches("\n  #").count(), matchers.len());
+        assert!(trace.ends_with("=> error UnrecognizedToken\n"));
     }
-}
 
+    #[test]
+    fn test_token_as_tuple() {
+        use crate::{str2sym, tokenize};
+
+        let srcfile = SrcFileInfo::from_str("a = b;".to_owned());
+        let tokens = tokenize(&srcfile, &js_rules::MATCHERS[..]).unwrap();
+
+        let expected = [
+            ("id", "a", 0, 1),
+            ("sp", " ", 1, 2),
+            ("assign", "=", 2, 3),
+            ("sp", " ", 3, 4),
+            ("id", "b", 4, 5),
+            ("semi", ";", 5, 6),
+        ]
+        .map(|(name, value, from, end)| {
+            (name.to_owned(), value.to_owned(), from, end)
+        });
+        assert_eq!(
+            tokens.iter().map(Token::as_tuple).collect::<Vec<_>>(),
+            expected
+        );
+
+        assert_eq!(
+            tokens[2].parts(),
+            (str2sym("assign"), str2sym("="), Span { from: 2, end: 3 })
+        );
+    }
 
-////////////////////////////////////////////////////////////////////////////////
-//// Tokenizer2
+    #[test]
+    fn test_operator_table() {
+        use crate::{
+            lazy_static::lazy_static,
+            prelude::{operator_table, OperatorTable},
+            tokenize, tokenize_boxed, BoxFnMatcher, FnMatcher,
+            TokenClassifier, TokenKind, TokenMatchResult,
+        };
 
-////////////////////////////////////////////////////////////////////////////////
-//// Char Matcher (used for string splitter)
+        let srcfile = SrcFileInfo::from_str("a===b==c=d!=e+=f".to_owned());
+        let mut matchers: Vec<BoxFnMatcher> = vec![Box::new(js_rules::id_m)];
+        matchers.extend(operator_table(&["=", "==", "===", "!=", "+="]));
+
+        let tokens = tokenize_boxed(&srcfile, &matchers).unwrap();
+        let ops = tokens
+            .iter()
+            .filter(|tok| !tok.check_name("id"))
+            .map(|tok| (tok.name_string(), tok.value_string()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            ops,
+            [
+                ("realeq", "==="),
+                ("eq", "=="),
+                ("assign", "="),
+                ("neq", "!="),
+                ("add_assign", "+="),
+            ]
+            .map(|(name, op)| (name.to_owned(), op.to_owned()))
+        );
+
+        // any input order
+        for ops in [["=", "==", "==="], ["===", "=", "=="], ["==", "===", "="]] {
+            let matchers = operator_table(&ops);
+            let srcfile = SrcFileInfo::from_str("=====".to_owned());
+            let names = tokenize_boxed(&srcfile, &matchers)
+                .unwrap()
+                .iter()
+                .map(|tok| tok.name_string())
+                .collect::<Vec<_>>();
 
-pub trait CharMatcher {
-    fn is_match(&self, c: char) -> bool;
-}
+            assert_eq!(names, ["realeq", "eq"], "{ops:?}");
+        }
 
-/// Simple Char Matcher
-pub struct SimpleCharMatcher {
-    target: char,
-}
+        // through a plain `FnMatcher`, the generated names classified
+        fn op_m(source: &str, from: usize) -> Option<TokenMatchResult> {
+            lazy_static! {
+                static ref OPS: OperatorTable =
+                    OperatorTable::new(&["=", "+="]);
+            }
 
-impl SimpleCharMatcher {
-    pub fn new(s: &str) -> Self {
-        Self {
-            target: s.chars().nth(0).unwrap(),
+            OPS.fetch_tok(source, from)
         }
+
+        let srcfile = SrcFileInfo::from_str("a=b+=c".to_owned());
+        let tokens =
+            tokenize(&srcfile, &[js_rules::id_m, op_m as FnMatcher]).unwrap();
+        let classifier = OperatorTable::new(&["=", "+="])
+            .classify_with(TokenClassifier::new());
+
+        assert!(tokens[1].check_name("assign"));
+        assert!(tokens[3].check_name("add_assign"));
+        assert!(tokens
+            .iter()
+            .filter(|tok| !tok.check_name("id"))
+            .all(|tok| tok.kind(&classifier) == TokenKind::Operator));
+        assert_eq!(
+            tokens[3].kind(&TokenClassifier::new()),
+            TokenKind::Other
+        );
     }
-}
 
-impl CharMatcher for SimpleCharMatcher {
-    #[inline]
-    fn is_match(&self, c: char) -> bool {
-        self.target == c
+    #[test]
+    fn test_heredoc_quoted_terminator() {
+        use crate::prelude::{heredoc_m, FLAG_RAW};
+
+        let tok = heredoc_m("<<EOF\n$a\\n\nEOF\nls", 0).unwrap().unwrap();
+        assert!(tok.check_value("$a\\n"));
+        assert!(!tok.has_flag(FLAG_RAW));
+        assert_eq!(tok.span(), Span { from: 0, end: 14 });
+
+        for source in ["<<'EOF'\n$a\\n\nEOF\nls", "<< \"EOF\"\n$a\\n\nEOF\nls"] {
+            let tok = heredoc_m(source, 0).unwrap().unwrap();
+
+            assert!(tok.check_value("$a\\n"), "{source:?}");
+            assert!(tok.has_flag(FLAG_RAW), "{source:?}");
+            assert_eq!(tok.span().end, source.len() - 3);
+        }
+
+        // closed by the bare terminator only
+        assert!(heredoc_m("<<'EOF'\nbody\n'EOF'\n", 0).is_none());
     }
-}
 
-pub struct RegexCharMatcher {
-    pat: Regex,
-}
+    #[test]
+    fn test_detect_newline_style() {
+        use crate::NewlineStyle;
 
-impl RegexCharMatcher {
-    pub fn new(patstr: &str) -> Self {
-        Self {
-            pat: Regex::new(patstr).unwrap(),
+        let style = |s: &str| {
+            SrcFileInfo::from_str(s.to_owned()).detect_newline_style()
+        };
+
+        assert_eq!(style("a\nb\n"), NewlineStyle::Lf);
+        assert_eq!(style("a"), NewlineStyle::Lf);
+        assert_eq!(style("a\r\nb\r\n"), NewlineStyle::CrLf);
+        assert_eq!(style("a\rb\r"), NewlineStyle::Cr);
+        assert_eq!(style("a\r\nb\nc"), NewlineStyle::Mixed);
+        assert_eq!(style("a\rb\r\n"), NewlineStyle::Mixed);
+    }
+
+    #[test]
+    fn test_tokenize_from() {
+        use crate::{tokenize, tokenize_from};
+
+        let srcfile =
+            SrcFileInfo::from_str("let a = 1;\nlet s = \"中\" + b;\n".to_owned());
+        let matchers = &js_rules::MATCHERS[..];
+        let full = tokenize(&srcfile, matchers).unwrap();
+
+        // from the second `let`, and from the `+` following a multi-bytes char
+        for start in [11, 25] {
+            let i = full.iter().position(|tok| tok.span.from == start).unwrap();
+            let suffix = tokenize_from(&srcfile, matchers, start).unwrap();
+
+            assert_eq!(suffix.len(), full.len() - i);
+            for (lhs, rhs) in suffix.iter().zip(full[i..].iter()) {
+                assert_eq!(lhs.as_tuple(), rhs.as_tuple());
+                assert_eq!(lhs.char_from, rhs.char_from);
+            }
         }
+
+        assert!(tokenize_from(&srcfile, matchers, srcfile.get_srcstr().len())
+            .unwrap()
+            .is_empty());
+
+        // inside `中`
+        let err = tokenize_from(&srcfile, matchers, 21).unwrap_err();
+        assert!(matches!(err.reason, TokenizeErrorReason::BadStart));
+        assert_eq!(err.start, 20);
     }
-}
 
-impl CharMatcher for RegexCharMatcher {
-    #[inline]
-    fn is_match(&self, c: char) -> bool {
-        self.pat.is_match(&c.to_string())
+    #[test]
+    fn test_splice_tokens() {
+        use crate::{prelude::splice_tokens, tokenize};
+
+        let srcfile = SrcFileInfo::from_str("x++;".to_owned());
+        let mut tokens =
+            tokenize(&srcfile, &js_rules::MATCHERS[..]).unwrap();
+
+        // `x++` => `x+=1`
+        let replacement = vec![
+            Token::new("add_assign", "+=", Span { from: 1, end: 3 }),
+            Token::new("lit_int", "1", Span { from: 3, end: 4 }),
+        ];
+        assert!(splice_tokens(&mut tokens, 1..2, replacement, true));
+
+        let names =
+            tokens.iter().map(|tok| tok.name_string()).collect::<Vec<_>>();
+        assert_eq!(names, ["id", "add_assign", "lit_int", "semi"]);
+        assert!(tokens.windows(2).all(|w| w[0].span.end == w[1].span.from));
+        assert_eq!(tokens[3].span, Span { from: 4, end: 5 });
+
+        // unshifted
+        let mut tokens =
+            tokenize(&srcfile, &js_rules::MATCHERS[..]).unwrap();
+        splice_tokens(&mut tokens, 0..1, vec![Token::eof()], false);
+        assert_eq!(tokens[2].span, Span { from: 3, end: 4 });
+
+        // `x` => `` shifting `++;` below zero (spans of another source)
+        let mut tokens = vec![
+            Token::new("id", "x", Span { from: 0, end: 5 }),
+            Token::new("semi", ";", Span { from: 1, end: 2 }),
+        ];
+        assert!(!splice_tokens(&mut tokens, 0..1, vec![], true));
+        assert_eq!(tokens.len(), 2);
     }
-}
 
-pub type FnCharMatcher = fn(char) -> bool;
-pub type LexDFAMap = HashMap<Symbol, Vec<(FnCharMatcher, (Symbol, bool))>>;
+    #[test]
+    fn test_token_same_name() {
+        use crate::{interner_len, tokenize, Symbol};
+
+        let srcfile = SrcFileInfo::from_str("let a = a;".to_owned());
+        let tokens = tokenize(&srcfile, &js_rules::MATCHERS[..]).unwrap();
+        let (let_, a1, a2) = (tokens[0], tokens[2], tokens[6]);
+
+        assert!(let_.same_name(&a1) && a1.same_name(&a2));
+        assert!(a1.same_value(&a2));
+        assert!(!let_.same_value(&a1));
+        assert!(!a1.same_name(&tokens[4]));
+
+        // keyword by id
+        let interned = interner_len();
+        let kw_let = Symbol::from_str_interned("let");
+        assert_eq!(interner_len(), interned);
+        assert!(let_.value == kw_let && a1.value != kw_let);
+    }
 
-#[allow(unused)]
-pub const ENTRY_ST: &'static str = "Entry";
+    #[test]
+    fn test_balanced_m() {
+        use crate::prelude::balanced_m;
+
+        let tok = balanced_m("(a (b) c) d", 3, '(', ')', None)
+            .unwrap()
+            .unwrap();
+        assert!(tok.check_value("a (b) c"));
+        assert_eq!(tok.span, Span { from: 3, end: 12 });
+
+        // escaped close
+        let tok = balanced_m(r"(a \) b)", 0, '(', ')', Some('\\'))
+            .unwrap()
+            .unwrap();
+        assert!(tok.check_value(r"a \) b"));
+
+        assert!(matches!(
+            balanced_m("(a (b", 0, '(', ')', None),
+            Some(Err(TokenizeErrorReason::Unterminated))
+        ));
+        assert!(balanced_m("a (b)", 0, '(', ')', None).is_none());
+    }
 
-pub struct LexDFA<'a> {
-    map: &'a LexDFAMap,
-    st: Symbol,
-}
+    #[test]
+    fn test_sub_tokenize() {
+        use crate::{
+            prelude::{sub_tokenize, tokenize_str},
+            tokenize,
+        };
 
-impl<'a> LexDFA<'a> {
-    pub fn new(map: &'a LexDFAMap) -> Self {
-        Self {
-            map,
-            st: str2sym(ENTRY_ST),
+        let srcfile = SrcFileInfo::from_str("f(`x + y * 2`);".to_owned());
+        let source = srcfile.get_srcstr();
+        let tokens = tokenize(&srcfile, &js_rules::MATCHERS[..]).unwrap();
+        let tmpl = tokens.iter().find(|tok| tok.check_name("aqstr")).unwrap();
+
+        let inner =
+            sub_tokenize(tmpl, source, 1, 1, &js_rules::MATCHERS[..])
+                .unwrap();
+
+        // spans point into the whole source
+        for tok in inner.iter() {
+            assert_eq!(
+                &source[tok.span.from..tok.span.end],
+                tok.value_string()
+            );
         }
+        let y = inner.iter().find(|tok| tok.check_value("y")).unwrap();
+        assert_eq!(y.span, Span { from: 7, end: 8 });
+        assert_eq!(inner[0].span.from, 3);
+        assert_eq!(inner.last().unwrap().span.end, 12);
+
+        // positions in `source`
+        let source = "f(\"é\", `x @`);";
+        let tmpl = &tokenize_str(source, &js_rules::MATCHERS[..]).unwrap()[5];
+        let err = sub_tokenize(tmpl, source, 1, 1, &js_rules::MATCHERS[..])
+            .unwrap_err();
+        assert_eq!(err.start, 10);
+        assert_eq!(err.src().get_srcstr(), source);
+
+        // a token of another source
+        assert!(sub_tokenize(tmpl, "f(`", 1, 1, &js_rules::MATCHERS[..])
+            .is_err());
     }
 
-    // Token END?
-    pub fn forward(&mut self, ch: char) -> bool {
-        let items = self.map.get(&self.st).unwrap();
+    /// Random UTF-8 sources, tokenize never panics
+    #[test]
+    fn test_tokenize_no_panic() {
+        use crate::{
+            prelude::{
+                balanced_m, block_comment_m, char_lit_m, dqstr_m, float_m,
+                heredoc_m, lit_regex_m, tokenize_str, FloatForms,
+            },
+            tokenize_iter, tokenize_recover_sync,
+        };
 
-        for (matcher, (sym, res)) in items.into_iter() {
-            if matcher(ch) {
-                self.st = *sym;
-                return *res;
-            }
-        }
+        const POOL: &[char] = &[
+            'a', 'Z', '_', '0', '9', 'x', '.', '+', '-', '*', '/', '=', '!',
+            '<', '>', '&', '|', '(', ')', '[', ']', '{', '}', ';', ',', ':',
+            '?', '"', '\'', '`', '\\', ' ', '\t', '\n', '\r', '#', '$', 'é',
+            '中', '\u{301}', '😀', '\0',
+        ];
+
+        // xorshift, reproducible
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
 
-        unreachable!("uncoverd char: <{}> on {{{}}}", ch, sym2str(self.st));
-    }
-}
+        for _ in 0..1000 {
+            let len = (next() % 24) as usize;
+            let src = (0..len)
+                .map(|_| POOL[(next() % POOL.len() as u64) as usize])
+                .collect::<String>();
+            let matchers = &js_rules::MATCHERS[..];
 
-#[macro_export]
-macro_rules! declare_st {
-    ( $($name:ident),* ) => {
-        use $crate::concat_idents2;
+            if let Err(err) = tokenize_str(&src, matchers) {
+                let _ = err.to_string();
+            }
 
-        $(
-            concat_idents2!(state_name = $name, _ST {
-                const state_name: &'static str = stringify!($name);
-            });
-        )*
-    };
-}
+            let srcfile = SrcFileInfo::from_str(src.clone());
+            // rendering probes the expected tokens, once is enough
+            let errors = tokenize_iter(&srcfile, matchers)
+                .filter_map(Result::err)
+                .collect::<Vec<_>>();
+            if let Some(err) = errors.last() {
+                let _ = err.to_string();
+            }
+            let _ = tokenize_recover_sync(&srcfile, matchers, &["semi"]);
+            let _ = tokenize2_ident(&src);
 
+            for offset in 0..=src.len() + 1 {
+                let _ = srcfile.linestr(offset);
+                let _ = srcfile.boffset2srcloc(offset);
+            }
 
-#[macro_export]
-macro_rules! lexdfamap {
-    ( $($cur_st:expr =>
-        {
-            $( $matcher:ident | $nxt_st:expr, $flag:literal )*
+            for (from, _) in src.char_indices() {
+                let rest = &src[from..];
+
+                let _ = char_lit_m(rest, from);
+                let _ = dqstr_m(rest, from);
+                let _ = float_m(FloatForms::default())(rest, from);
+                let _ = heredoc_m(rest, from);
+                let _ = block_comment_m("/*", "*/")(rest, from);
+                let _ = balanced_m(rest, from, '(', ')', Some('\\'));
+                let _ = lit_regex_m(rest, from);
+            }
         }
-      ),*
-    ) => {
-        {
-            use std::collections::HashMap;
-            use $crate::FnCharMatcher;
-            use $crate::concat_idents2;
-            use $crate::str2sym;
+    }
 
-            let mut _map = HashMap::new();
+    #[test]
+    fn test_unrecognized_expected() {
+        use crate::{prelude::tokenize_str, str2sym};
 
-            $(
-                let mut trans_vec = Vec::new();
+        let err = tokenize_str("a = @1;", &js_rules::MATCHERS[..]).unwrap_err();
+        let expected = err.expected();
 
-                $(
-                    let nxt_st = str2sym($nxt_st);
+        for name in ["id", "lit_int", "lparen", "sp", "semi"] {
+            assert!(expected.contains(&str2sym(name)), "{name}");
+        }
+        assert!(err
+            .to_string()
+            .contains("expected one of: id, lit_int, "));
 
-                    trans_vec.push((
-                        concat_idents2!(matcher_name = $matcher, _m {
-                            matcher_name as FnCharMatcher
-                        }),
-                        (nxt_st, $flag)
-                    ));
-                )*
+        // other errors have no hint
+        let err = tokenize_str("\"abc", &js_rules::MATCHERS[..]).unwrap_err();
+        assert!(err.expected().is_empty());
 
-                let cur_st = str2sym($cur_st);
+        // boxed matchers, and into a `Send + Sync` error
+        type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
-                _map.insert(cur_st, trans_vec);
-            )*
+        fn boxed_err() -> Result<(), BoxError> {
+            let srcfile = SrcFileInfo::from_str("a @".to_owned());
+            let matchers: Vec<crate::BoxFnMatcher> =
+                vec![Box::new(js_rules::id_m), Box::new(js_rules::sp_m)];
 
-            _map
+            crate::tokenize_boxed(&srcfile, &matchers)?;
+            Ok(())
         }
+
+        let err = boxed_err().unwrap_err();
+        let err = err.downcast_ref::<crate::TokenizeError>().unwrap();
+        assert_eq!(err.expected(), [str2sym("id"), str2sym("sp")]);
     }
-}
 
+    #[test]
+    fn test_span_range() {
+        use std::ops::Range;
 
-pub struct TokenRecognizer {
-    pub lookhead: usize,
-    pub pat_items: Vec<(Regex, Symbol)>,
-}
+        let span = Span { from: 2, end: 5 };
+        let range: Range<usize> = span.into();
 
-impl TokenRecognizer {
-    pub fn recognize(&self, source: &str, span: Span) -> Token {
-        let end = min(span.end, span.from + self.lookhead);
-
-        for (pat, name) in self.pat_items.iter() {
-            if pat.is_match(&source[..end]) {
-                return Token {
-                    name: *name,
-                    value: str2sym(&source[span.from..span.end]),
-                    span,
-                };
-            }
-        }
+        assert_eq!(range, 2..5);
+        assert_eq!(Span::from(range), span);
 
-        unreachable!("Unreconized Raw Token: {}", &source[span.from..span.end])
+        let source = "a = bcd;";
+        assert_eq!(span.index_into(source), "= b");
+        assert_eq!(&source[Range::from(span)], "= b");
     }
-}
 
+    #[test]
+    fn test_matchers_from_rules() {
+        use crate::{prelude::matchers_from_rules, tokenize_boxed};
 
-#[macro_export]
-macro_rules! token_recognizer {
-    ( $lookahead:literal | $($token_name:ident => $patstr:literal),* | ) => {
-        {
-            use $crate::Regex;
-            use $crate::str2sym;
-            use $crate::TokenRecognizer;
+        let rules = [("key", "[a-z]+"), ("eq", "="), ("sp", "[ ]+")]
+            .map(|(name, patstr)| (name.to_owned(), patstr.to_owned()));
+        let matchers = matchers_from_rules(&rules).unwrap();
 
-            let mut pat_items = vec![];
+        let srcfile = SrcFileInfo::from_str("name = value".to_owned());
+        let tokens = tokenize_boxed(&srcfile, &matchers).unwrap();
+        let names =
+            tokens.iter().map(|tok| tok.name_string()).collect::<Vec<_>>();
 
-            $(
-                let mut patstr = $patstr.to_owned();
+        assert_eq!(names, ["key", "sp", "eq", "sp", "key"]);
+        assert_eq!(tokens[4].span, Span { from: 7, end: 12 });
 
-                if patstr.starts_with("^") {
-                    patstr.insert(0, '^')
-                }
+        let srcfile = SrcFileInfo::from_str("a=1".to_owned());
+        let err = tokenize_boxed(&srcfile, &matchers).unwrap_err();
+        assert_eq!(err.start, 2);
 
-                pat_items.push((
-                    Regex::new(&patstr).unwrap(),
-                    str2sym(stringify!($token_name))
-                ));
-            )*
+        let bad = [("broken".to_owned(), "(".to_owned())];
+        assert!(matchers_from_rules(&bad).is_err());
+    }
 
-            TokenRecognizer {
-                lookhead: $lookahead,
-                pat_items
-            }
-        }
+    #[test]
+    fn test_tokenize_boxed() {
+        use crate::{prelude::line_comment_m, tokenize_boxed, BoxFnMatcher};
+
+        // configured at runtime
+        let prefix = String::from("--");
+
+        let matchers: Vec<BoxFnMatcher> = vec![
+            Box::new(move |source, from| line_comment_m(&prefix)(source, from)),
+            Box::new(js_rules::id_m),
+            Box::new(js_rules::sp_m),
+            Box::new(js_rules::newline_m),
+        ];
+
+        let srcfile = SrcFileInfo::from_str("abc -- note\nd".to_owned());
+        let tokens = tokenize_boxed(&srcfile, &matchers).unwrap();
+        let names =
+            tokens.iter().map(|tok| tok.name_string()).collect::<Vec<_>>();
+
+        assert_eq!(names, ["id", "sp", "line_comment", "newline", "id"]);
+        assert_eq!(tokens[2].value_string(), "-- note");
+        assert_eq!(tokens[2].span, Span { from: 4, end: 11 });
     }
-}
 
+    #[test]
+    fn test_coalesce() {
+        use crate::prelude::coalesce;
+
+        let sp = |from, end| Token::new("sp", " ", Span { from, end });
+
+        // `a  b c`, the `sp` between `a` and `b` split in two
+        let tokens = vec![
+            Token::new("id", "a", Span { from: 0, end: 1 }),
+            sp(1, 2),
+            sp(2, 3),
+            Token::new("id", "b", Span { from: 3, end: 4 }),
+            sp(4, 5),
+        ];
+        let tokens = coalesce(tokens, &["sp"]);
+
+        let names =
+            tokens.iter().map(|tok| tok.name_string()).collect::<Vec<_>>();
+        assert_eq!(names, ["id", "sp", "id", "sp"]);
+        assert_eq!(tokens[1].span, Span { from: 1, end: 3 });
+        assert_eq!(tokens[1].value_string(), "  ");
+
+        // not in `names`, or not adjacent
+        let tokens = vec![
+            Token::new("id", "a", Span { from: 0, end: 1 }),
+            Token::new("id", "b", Span { from: 1, end: 2 }),
+            sp(3, 4),
+            sp(5, 6),
+        ];
+        assert_eq!(coalesce(tokens, &["sp"]).len(), 4);
+    }
 
+    #[test]
+    fn test_display_with_loc() {
+        use crate::tokenize;
 
-pub fn tokenize2(
-    srcfile: &SrcFileInfo,
-    dfamap: &LexDFAMap,
-    reconizer: &TokenRecognizer,
-) -> TokenizeResult {
-    let mut tokens = vec![];
+        let srcfile = SrcFileInfo::from_str("let a;\n  a = 1;".to_owned());
+        let tokens = tokenize(&srcfile, &js_rules::MATCHERS[..]).unwrap();
+        let tok = tokens.iter().find(|tok| tok.check_value("1")).unwrap();
 
-    let mut dfa = LexDFA::new(dfamap);
-    let mut bytes_pos = 0;
-    let mut cache = String::new();
+        assert_eq!(tok.display_with_loc(&srcfile), "lit_int 1 @ (2, 7)");
+        assert_eq!(tokens[0].display_with_loc(&srcfile), "id let @ (1, 1)");
+    }
 
-    for c in srcfile.srcstr.chars() {
-        if dfa.forward(c) {
-            // REACH TOKEN END
-            // recognize token
-            let span = Span {
-                from: bytes_pos,
-                end: bytes_pos + cache.len(),
-            };
-            bytes_pos += span.len();
+    #[test]
+    fn test_number_sign() {
+        use crate::{
+            prelude::{number_m, number_sign_m, FLAG_FLOAT},
+            tokenize, FnMatcher,
+        };
+
+        // unsigned by default, the `-` is an operator
+        let matchers: [FnMatcher; 3] =
+            [js_rules::id_m, js_rules::sub_m, number_m];
+        let srcfile = SrcFileInfo::from_str("a-1".to_owned());
+        let tokens = tokenize(&srcfile, &matchers).unwrap();
+        let values =
+            tokens.iter().map(|tok| tok.value_string()).collect::<Vec<_>>();
+
+        assert_eq!(values, ["a", "-", "1"]);
+        assert_eq!(tokens[2].name_string(), "number");
+
+        // signed literal when enabled
+        let tok = number_sign_m(true)("-1.5;", 3).unwrap().unwrap();
+        assert!(tok.check_value("-1.5") && tok.has_flag(FLAG_FLOAT));
+        assert_eq!(tok.span, Span { from: 3, end: 7 });
+
+        assert!(number_sign_m(false)("-1", 0).is_none());
+        assert!(number_sign_m(true)("-x", 0).is_none());
+        let tok = number_sign_m(true)("+2", 0).unwrap().unwrap();
+        assert!(tok.check_value("+2"));
+    }
 
-            tokens.push(reconizer.recognize(&srcfile.srcstr, span));
+    #[test]
+    fn test_check_pattern() {
+        use crate::prelude::check_pattern;
 
-            cache.clear();
-        }
+        let warnings = check_pattern(r"[+|-]?([0-9]+)");
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("`[+|-]`"));
 
-        cache.push(c);
+        assert!(check_pattern(r"[+-]?([0-9]+)").is_empty());
+        assert!(check_pattern(r"\|\||[|]|a\[|b").is_empty());
+        assert!(check_pattern("[[:alpha:]_][[:alnum:]_]*").is_empty());
+        assert_eq!(check_pattern("[^]|a][[:digit:]|.]").len(), 2);
     }
 
-    Ok(tokens)
-}
+    #[test]
+    fn test_next_significant() {
+        use crate::{prelude::tokenize_str, TokenCursor};
 
+        // leading whitespace and comments kept in the stream
+        let tokens =
+            tokenize_str("  // c\n\ta  // d\n", &js_rules::MATCHERS[..])
+                .unwrap();
+        let mut cursor = TokenCursor::new(&tokens);
 
+        let tok = cursor.next_significant();
+        assert!(tok.check_name("id") && tok.check_value("a"));
+        assert_eq!(cursor.pos(), 5);
 
-#[cfg(test)]
-mod tests {
+        assert!(cursor.next_significant().check_name("eof"));
+        assert!(cursor.is_eof());
+    }
 
     #[test]
-    fn test_error_info() {
-        println!("aaaa\n^^^^^")
+    #[should_panic(expected = "duplicated state key")]
+    fn test_lexdfamap_dup_state_value() {
+        // different keys, same state
+        const IDENT_ST2: &str = IDENT_NAME_ST;
+
+        let _dfamap = lexdfamap! {
+            IDENT_NAME_ST => {
+                ident | IDENT_NAME_ST, false
+            },
+            IDENT_ST2 => {
+                sp    | BLANK_ST,      true
+            }
+        };
     }
 }
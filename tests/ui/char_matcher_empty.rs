@@ -0,0 +1,8 @@
+use m6lexerkit::make_char_matcher_rules;
+
+make_char_matcher_rules! {
+    alpha => "[[:alpha:]]" | r,
+    quote => ""            | n
+}
+
+fn main() {}
@@ -0,0 +1,26 @@
+use m6lexerkit::{declare_st, lexdfamap, make_char_matcher_rules, ENTRY_ST};
+
+make_char_matcher_rules! {
+    alpha => "[[:alpha:]]" | r,
+    sp    => "[[:space:]]" | r
+}
+
+declare_st! {
+    BLANK,
+    IDENT
+}
+
+fn main() {
+    let _map = lexdfamap! {
+        ENTRY_ST => {
+            alpha | IDENT_ST, false
+        },
+        IDENT_ST => {
+            alpha | IDENT_ST, false
+            sp    | BLANK_ST, true
+        },
+        IDENT_ST => {
+            sp    | BLANK_ST, true
+        }
+    };
+}